@@ -0,0 +1,276 @@
+//! Bounded extraction of untrusted tar archives.
+
+use async_compression::futures::bufread::{BrotliDecoder, GzipDecoder, ZstdDecoder};
+
+use async_std::fs::{self, OpenOptions};
+use async_std::io::{self, Read};
+use async_std::prelude::*;
+use async_tar::{Archive, EntryType};
+use std::collections::VecDeque;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config;
+use crate::CONFIG;
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+const ENTRY_OVERHEAD: u64 = 2048; // in bytes, header and padding of an entry, with room for long names
+const MAX_LINK_DEPTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Brotli,
+    Gzip,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    /// Brotli streams have no magic bytes, so anything unrecognized is
+    /// taken as brotli, which is what the master always sent.
+    pub fn detect(buf: &[u8]) -> Self {
+        if buf.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if buf.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if buf.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+            Compression::None
+        } else {
+            Compression::Brotli
+        }
+    }
+}
+
+/// Decompresses `buf` as detected by `Compression::detect` and unpacks it into `root`.
+pub async fn extract_compressed(buf: &[u8], root: &Path) -> Result<(), String> {
+    let compression = Compression::detect(buf);
+    debug!("Unpacking a {:?} compressed archive", compression);
+    let limits = &CONFIG.archive;
+    match compression {
+        Compression::Brotli => extract_bounded(BrotliDecoder::new(buf), root, limits).await,
+        Compression::Gzip => extract_bounded(GzipDecoder::new(buf), root, limits).await,
+        Compression::Zstd => extract_bounded(ZstdDecoder::new(buf), root, limits).await,
+        Compression::None => extract_bounded(buf, root, limits).await,
+    }
+}
+
+/// Unpacks at most `max_size` bytes and the headers of `max_entries` entries
+/// from `reader`, so metadata buffered by async-tar can't grow unbounded either.
+pub async fn extract_bounded<R: Read + Unpin + Send + Sync>(
+    reader: R,
+    root: &Path,
+    limits: &config::Archive,
+) -> Result<(), String> {
+    let slack = (limits.max_entries as u64).saturating_mul(ENTRY_OVERHEAD);
+    let mut reader = reader.take(limits.max_size.saturating_add(slack));
+    let res = extract(&mut reader, root, limits).await;
+    if reader.limit() == 0 {
+        return Err(format!("Archive is larger than {} bytes", limits.max_size));
+    }
+    res
+}
+
+/// async-tar hands out `async_std` paths.
+fn std_path<P: AsRef<Path> + ?Sized>(path: &P) -> PathBuf {
+    path.as_ref().to_path_buf()
+}
+
+/// Joins `path` to `root` if it stays inside, without touching the file system.
+fn contained(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(root.join(out))
+}
+
+/// Resolves the `target` of a link placed in `dir` against the links already
+/// unpacked under `root`. Fails if it leaves `root` at any step, or goes on
+/// through a path that doesn't exist yet and could become a link later.
+async fn resolve_link(root: &Path, dir: &Path, target: &Path) -> Option<PathBuf> {
+    let mut current = dir.to_path_buf();
+    let mut pending: VecDeque<PathBuf> = target.components().map(|c| std_path(&c)).collect();
+    let (mut depth, mut missing) = (0, false);
+    while let Some(part) = pending.pop_front() {
+        if missing {
+            return None;
+        }
+        match part.components().next()? {
+            Component::Normal(name) => {
+                current.push(name);
+                match fs::symlink_metadata(&current).await {
+                    Ok(meta) if meta.file_type().is_symlink() => {
+                        depth += 1;
+                        if depth > MAX_LINK_DEPTH {
+                            return None;
+                        }
+                        let next = std_path(&fs::read_link(&current).await.ok()?);
+                        current.pop();
+                        for component in next.components().rev() {
+                            pending.push_front(std_path(&component));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) => missing = true,
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if current == root {
+                    return None;
+                }
+                current.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(current)
+}
+
+/// Fails if `relative` or any directory on the way to it from `root` is a
+/// symlink, so paths named by an archive are never followed out of `root`.
+pub fn check_no_links(root: &Path, relative: &Path) -> Result<(), String> {
+    let mut current = root.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        if let Ok(meta) = std::fs::symlink_metadata(&current) {
+            if meta.file_type().is_symlink() {
+                return Err(format!("{} goes through a symlink", relative.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails if any directory between `root` and `path` is a symlink,
+/// so entries can never be written through a link.
+async fn check_parents(root: &Path, path: &Path) -> Result<(), String> {
+    let mut current = root.to_path_buf();
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        if components.peek().is_none() {
+            break;
+        }
+        current.push(component);
+        if let Ok(meta) = fs::symlink_metadata(&current).await {
+            if meta.file_type().is_symlink() {
+                return Err(format!("{} goes through a symlink", relative.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unpacks `reader` into `root`. Absolute paths, `..`, links leaving `root`,
+/// device files and archives over the size or entry limit are rejected.
+pub async fn extract<R: Read + Unpin + Send + Sync>(
+    reader: R,
+    root: &Path,
+    limits: &config::Archive,
+) -> Result<(), String> {
+    let archive = Archive::new(reader);
+    let mut entries = archive
+        .entries()
+        .map_err(|err| format!("Unable to read archive: {}", err))?;
+    let (mut total, mut count) = (0u64, 0usize);
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry.map_err(|err| format!("Unable to read archive: {}", err))?;
+        let entry_type = entry.header().entry_type();
+        if matches!(entry_type, EntryType::XGlobalHeader | EntryType::XHeader) {
+            continue;
+        }
+        count += 1;
+        if count > limits.max_entries {
+            return Err(format!(
+                "Archive has more than {} entries",
+                limits.max_entries
+            ));
+        }
+        let name = std_path(
+            &*entry
+                .path()
+                .map_err(|err| format!("Invalid path in archive: {}", err))?,
+        );
+        let path = contained(root, &name)
+            .ok_or_else(|| format!("{} is outside of the archive", name.display()))?;
+        if path == root {
+            continue;
+        }
+        check_parents(root, &path).await?;
+        match entry_type {
+            EntryType::Directory => {
+                fs::create_dir_all(&path)
+                    .await
+                    .map_err(|err| format!("Unable to create {}: {}", name.display(), err))?;
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let size = entry
+                    .header()
+                    .size()
+                    .map_err(|err| format!("Invalid size of {}: {}", name.display(), err))?;
+                total = total.saturating_add(size);
+                if total > limits.max_size {
+                    return Err(format!(
+                        "Archive is larger than {} bytes",
+                        limits.max_size
+                    ));
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .map_err(|err| format!("Unable to create {}: {}", name.display(), err))?;
+                }
+                let mode = entry.header().mode().unwrap_or(0o644) & 0o755;
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                async_std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+                let mut file = options
+                    .open(&path)
+                    .await
+                    .map_err(|err| format!("Unable to create {}: {}", name.display(), err))?;
+                io::copy(&mut (&mut entry).take(size), &mut file)
+                    .await
+                    .map_err(|err| format!("Unable to extract {}: {}", name.display(), err))?;
+                file.flush().await.ok();
+            }
+            EntryType::Symlink => {
+                let target = std_path(
+                    &*entry
+                        .link_name()
+                        .map_err(|err| format!("Invalid link in archive: {}", err))?
+                        .ok_or_else(|| format!("{} has no link target", name.display()))?,
+                );
+                let parent = path.parent().unwrap_or(root);
+                fs::create_dir_all(parent)
+                    .await
+                    .map_err(|err| format!("Unable to create {}: {}", name.display(), err))?;
+                if target.is_absolute() || resolve_link(root, parent, &target).await.is_none() {
+                    return Err(format!("{} links outside of the archive", name.display()));
+                }
+                async_std::os::unix::fs::symlink(&target, &path)
+                    .await
+                    .map_err(|err| format!("Unable to create {}: {}", name.display(), err))?;
+            }
+            _ => {
+                return Err(format!(
+                    "{} has unsupported type {:?}",
+                    name.display(),
+                    entry_type
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Cache directory on local disk. Entries are `<blake3><created at><payload>`,
+//! written atomically, and evicted by least recent use over `cache.max_size`.
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::constants::CACHE_TEMP_PATH;
+use crate::lru;
+use crate::CONFIG;
+
+const HEADER_LEN: usize = blake3::OUT_LEN + 8;
+
+fn entry_name(key: &str) -> String {
+    key.replace(':', "-")
+}
+
+fn entry_path(key: &str) -> PathBuf {
+    PathBuf::from(&CONFIG.cache.path).join(entry_name(key))
+}
+
+fn expired(created: u64) -> bool {
+    if CONFIG.cache.ttl == 0 {
+        return false;
+    }
+    let created = UNIX_EPOCH + Duration::from_secs(created);
+    match SystemTime::now().duration_since(created) {
+        Ok(age) => age.as_secs() > CONFIG.cache.ttl,
+        Err(_) => false,
+    }
+}
+
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    let path = entry_path(key);
+    let data = fs::read(&path).ok()?;
+    if data.len() < HEADER_LEN
+        || blake3::hash(&data[blake3::OUT_LEN..]).as_bytes()[..] != data[..blake3::OUT_LEN]
+    {
+        warn!(
+            "Cache entry {} is corrupted. Removing it ...",
+            path.display()
+        );
+        fs::remove_file(&path).ok();
+        return None;
+    }
+    let mut created = [0u8; 8];
+    created.copy_from_slice(&data[blake3::OUT_LEN..HEADER_LEN]);
+    if expired(u64::from_le_bytes(created)) {
+        fs::remove_file(&path).ok();
+        return None;
+    }
+    lru::touch(&path).ok();
+    Some(data[HEADER_LEN..].to_vec())
+}
+
+pub fn set(key: &str, value: Vec<u8>) {
+    if let Err(err) = write_entry(key, &value) {
+        warn!("Unable to store cache entry {}: {}", key, err);
+        return;
+    }
+    lru::evict(Path::new(&CONFIG.cache.path), CONFIG.cache.max_size);
+}
+
+fn write_entry(key: &str, value: &[u8]) -> std::io::Result<()> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+        .to_le_bytes();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&created);
+    hasher.update(value);
+    let mut temp = lru::temp_file(&Path::new(&CONFIG.cache.path).join(CACHE_TEMP_PATH))?;
+    temp.write_all(hasher.finalize().as_bytes())?;
+    temp.write_all(&created)?;
+    temp.write_all(value)?;
+    lru::persist(temp, &entry_path(key))
+}
+
+/// Removes every entry whose key starts with `prefix`.
+pub fn purge(prefix: &str) -> usize {
+    let prefix = entry_name(prefix);
+    let mut removed = 0;
+    if let Ok(dir) = fs::read_dir(&CONFIG.cache.path) {
+        for entry in dir.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix)
+                && fs::remove_file(entry.path()).is_ok()
+            {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
@@ -0,0 +1,61 @@
+mod disk;
+mod remote;
+
+use crate::config::CacheBackend;
+use crate::constants::CACHE_PREFIX;
+use crate::CONFIG;
+
+pub fn enabled() -> bool {
+    CONFIG.cache_backend() != CacheBackend::None
+}
+
+/// Hashes `parts` as one value. Every part is length-prefixed,
+/// so different splits of the same bytes never collide.
+pub fn hash_parts(parts: &[&[u8]]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    for part in parts {
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+/// Hashes `parts` into a single key.
+pub fn make_key(namespace: &str, parts: &[&[u8]]) -> String {
+    format!(
+        "{}{}:{}",
+        CACHE_PREFIX,
+        namespace,
+        hash_parts(parts).to_hex()
+    )
+}
+
+pub async fn get(key: &str) -> Option<Vec<u8>> {
+    match CONFIG.cache_backend() {
+        CacheBackend::Redis => remote::get(key).await,
+        CacheBackend::Disk => disk::get(key),
+        CacheBackend::None => None,
+    }
+}
+
+pub async fn set(key: &str, value: Vec<u8>) {
+    match CONFIG.cache_backend() {
+        CacheBackend::Redis => remote::set(key, value).await,
+        CacheBackend::Disk => disk::set(key, value),
+        CacheBackend::None => {}
+    }
+}
+
+/// Removes every entry of `namespace` (e.g. `compile`, `graders`),
+/// or every entry when it is `None`. The cache may be shared by other slaves.
+pub async fn purge(namespace: Option<&str>) -> usize {
+    let prefix = match namespace {
+        Some(namespace) => format!("{}{}:", CACHE_PREFIX, namespace),
+        None => String::from(CACHE_PREFIX),
+    };
+    match CONFIG.cache_backend() {
+        CacheBackend::Redis => remote::purge(&prefix).await,
+        CacheBackend::Disk => disk::purge(&prefix),
+        CacheBackend::None => 0,
+    }
+}
@@ -0,0 +1,140 @@
+use async_std::future::timeout;
+use async_std::sync::Mutex;
+use futures::StreamExt;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::constants::{CACHE_PURGE_BATCH, CACHE_RETRY_DELAY};
+use crate::CONFIG;
+
+/// Connection shared by every cache user. It is opened lazily, dropped on
+/// the first failed command and reopened on the next call, but not before
+/// `retry_at` so an unreachable server doesn't stall every lookup.
+#[derive(Default)]
+struct Shared {
+    con: Option<MultiplexedConnection>,
+    retry_at: Option<Instant>,
+}
+
+lazy_static! {
+    static ref SHARED: Mutex<Shared> = Mutex::new(Shared::default());
+}
+
+fn op_timeout() -> Duration {
+    Duration::from_millis(CONFIG.cache.timeout)
+}
+
+async fn open() -> Result<MultiplexedConnection, String> {
+    let info = CONFIG.redis.connection_info().map_err(|err| err.to_string())?;
+    let client = redis::Client::open(info).map_err(|err| err.to_string())?;
+    match timeout(op_timeout(), client.get_multiplexed_async_std_connection()).await {
+        Ok(Ok(con)) => Ok(con),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(String::from("connection timed out")),
+    }
+}
+
+/// Returns the shared connection, or `None` while redis is unreachable.
+async fn connection() -> Option<MultiplexedConnection> {
+    let mut shared = SHARED.lock().await;
+    if let Some(con) = &shared.con {
+        return Some(con.clone());
+    }
+    if let Some(retry_at) = shared.retry_at {
+        if Instant::now() < retry_at {
+            return None;
+        }
+    }
+    match open().await {
+        Ok(con) => {
+            if shared.retry_at.take().is_some() {
+                info!("Reconnected to redis server");
+            }
+            shared.con = Some(con.clone());
+            Some(con)
+        }
+        Err(err) => {
+            warn!(
+                "Unable to connect to redis server: {}. Caching is disabled for {} secs",
+                err, CACHE_RETRY_DELAY
+            );
+            shared.retry_at = Some(Instant::now() + Duration::from_secs(CACHE_RETRY_DELAY));
+            None
+        }
+    }
+}
+
+/// Forgets the shared connection so the next call reconnects.
+async fn reset() {
+    SHARED.lock().await.con = None;
+}
+
+/// Runs `op` with the cache timeout, resetting the connection when it fails.
+async fn with_timeout<T, F>(what: &str, op: F) -> Option<T>
+where
+    F: Future<Output = redis::RedisResult<T>>,
+{
+    match timeout(op_timeout(), op).await {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(err)) => {
+            warn!("Redis {} failed: {}", what, err);
+            if err.is_connection_dropped() || err.is_io_error() {
+                reset().await;
+            }
+            None
+        }
+        Err(_) => {
+            warn!("Redis {} timed out", what);
+            reset().await;
+            None
+        }
+    }
+}
+
+pub async fn get(key: &str) -> Option<Vec<u8>> {
+    let mut con = connection().await?;
+    with_timeout("lookup", con.get::<_, Option<Vec<u8>>>(key))
+        .await
+        .flatten()
+}
+
+pub async fn set(key: &str, value: Vec<u8>) {
+    let mut con = match connection().await {
+        Some(con) => con,
+        None => return,
+    };
+    if CONFIG.cache.ttl == 0 {
+        with_timeout("store", con.set::<_, _, ()>(key, value)).await;
+    } else {
+        with_timeout(
+            "store",
+            con.set_ex::<_, _, ()>(key, value, CONFIG.cache.ttl as usize),
+        )
+        .await;
+    }
+}
+
+/// Removes every key that starts with `prefix`.
+pub async fn purge(prefix: &str) -> usize {
+    let mut con = match connection().await {
+        Some(con) => con,
+        None => return 0,
+    };
+    let keys: Vec<String> = match con.scan_match::<_, String>(format!("{}*", prefix)).await {
+        Ok(iter) => iter.collect::<Vec<String>>().await,
+        Err(err) => {
+            error!("Unable to scan cache entries: {}", err);
+            return 0;
+        }
+    };
+    let mut removed = 0;
+    for chunk in keys.chunks(CACHE_PURGE_BATCH) {
+        match con.del::<_, usize>(chunk).await {
+            Ok(n) => removed += n,
+            Err(err) => error!("Unable to remove cache entries: {}", err),
+        }
+    }
+    removed
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::cache;
+use crate::constants::MAX_PARALLEL_RUNS;
+use crate::LANGUAGES;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LanguageInfo {
+    pub uuid: Uuid,
+    pub name: String,
+    pub version: String,
+    pub toolchain: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DisabledLanguage {
+    pub uuid: Uuid,
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JudgeMode {
+    Simple, // Command::GetJudge
+    Novel,  // Command::GetJudgev2
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Capabilities {
+    pub languages: Vec<LanguageInfo>,
+    pub disabled_languages: Vec<DisabledLanguage>,
+    pub modes: Vec<JudgeMode>,
+    pub max_parallel_runs: usize,
+    pub cache_enabled: bool,
+}
+
+impl Capabilities {
+    pub fn current() -> Self {
+        Self {
+            languages: LANGUAGES.describe(),
+            disabled_languages: LANGUAGES.disabled(),
+            modes: vec![JudgeMode::Simple, JudgeMode::Novel],
+            max_parallel_runs: MAX_PARALLEL_RUNS,
+            cache_enabled: cache::enabled(),
+        }
+    }
+}
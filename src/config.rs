@@ -1,4 +1,8 @@
+use redis::{ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisResult};
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::constants::*;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Host {
@@ -16,6 +20,13 @@ impl Host {
 pub struct Redis {
     pub enabled: bool,
     pub redis: Option<String>,
+    #[serde(default)]
+    pub tls_enabled: bool,
+    #[serde(default)]
+    pub tls_insecure: bool, // Skips certificate and hostname verification
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub db: Option<i64>,
 }
 
 impl Redis {
@@ -24,6 +35,263 @@ impl Redis {
             if self.redis.is_none() {
                 panic!("`redis` feature is enabled. but you didn't provide redis address.");
             }
+            let info = match self.connection_info() {
+                Ok(info) => info,
+                Err(err) => panic!("Invalid redis address {}: {}", self.redis.as_ref().unwrap(), err),
+            };
+            let tls = matches!(info.addr, ConnectionAddr::TcpTls { .. });
+            if self.tls_enabled && !tls {
+                panic!("`redis.tls_enabled` is set. but redis address is not a TCP address.");
+            }
+            if self.tls_insecure && !tls {
+                panic!("`redis.tls_insecure` requires `redis.tls_enabled` or a `rediss://` address.");
+            }
+            if self.username.is_some() && self.password.is_none() {
+                panic!("`redis.username` is set. but you didn't provide `redis.password`.");
+            }
+            if info.redis.db < 0 {
+                panic!("`redis.db` must not be negative.");
+            }
+        }
+    }
+
+    /// Parses `redis` and applies the TLS and authentication options on top of it.
+    /// Options set here take precedence over the ones in the address.
+    pub fn connection_info(&self) -> RedisResult<ConnectionInfo> {
+        let mut info = self.redis.as_deref().unwrap_or_default().into_connection_info()?;
+        if let ConnectionAddr::Tcp(host, port) = &info.addr {
+            if self.tls_enabled {
+                info.addr = ConnectionAddr::TcpTls {
+                    host: host.clone(),
+                    port: *port,
+                    insecure: self.tls_insecure,
+                };
+            }
+        } else if let ConnectionAddr::TcpTls { insecure, .. } = &mut info.addr {
+            *insecure |= self.tls_insecure;
+        }
+        if self.username.is_some() {
+            info.redis.username = self.username.clone();
+        }
+        if self.password.is_some() {
+            info.redis.password = self.password.clone();
+        }
+        if let Some(db) = self.db {
+            info.redis.db = db;
+        }
+        Ok(info)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Compile {
+    pub time_limit: f64,      // in secs
+    pub wall_time_limit: f64, // in secs
+    pub mem_limit: u64,       // in kb
+    pub procs: usize,
+    pub fsize_limit: u64,    // in kb
+    pub output_limit: usize, // in bytes, compiler stdout/stderr kept per stream
+    pub dirs: Vec<String>,   // Extra toolchain directories mounted into the sandbox
+}
+
+impl Default for Compile {
+    fn default() -> Self {
+        Self {
+            time_limit: COMPILE_TIME_LIMIT,
+            wall_time_limit: COMPILE_WALL_TIME_LIMIT,
+            mem_limit: COMPILE_MEM_LIMIT,
+            procs: COMPILE_PROCS,
+            fsize_limit: COMPILE_FSIZE_LIMIT,
+            output_limit: COMPILE_OUTPUT_LIMIT,
+            dirs: vec![],
+        }
+    }
+}
+
+impl Compile {
+    fn validate(&self) {
+        if self.time_limit <= 0.0 || self.mem_limit == 0 || self.procs == 0 {
+            panic!("`compile` limits must be greater than zero.");
+        }
+        if self.wall_time_limit < self.time_limit {
+            panic!("`compile.wall_time_limit` must not be less than `compile.time_limit`.");
+        }
+        for dir in self.dirs.iter() {
+            if !dir.starts_with('/') {
+                panic!("`compile.dirs` must be absolute paths: {}", dir);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    None,
+    Redis,
+    Disk,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Cache {
+    pub backend: Option<CacheBackend>, // Defaults to `redis` when `redis.enabled` is set
+    pub path: String,
+    pub max_size: u64, // in bytes, only for `disk`
+    pub ttl: u64,      // in secs, 0 keeps entries forever
+    pub timeout: u64,  // in ms, only for `redis`
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            path: String::from(CACHE_PATH),
+            max_size: CACHE_MAX_SIZE,
+            ttl: CACHE_TTL,
+            timeout: CACHE_TIMEOUT,
+        }
+    }
+}
+
+impl Cache {
+    fn validate(&self, redis: &Redis) {
+        match self.backend {
+            Some(CacheBackend::Redis) if !redis.enabled => {
+                panic!("`cache.backend` is `redis`. but `redis` feature is disabled.");
+            }
+            Some(CacheBackend::Redis) | None if redis.enabled && self.timeout == 0 => {
+                panic!("`cache.timeout` must be greater than zero.");
+            }
+            Some(CacheBackend::Disk) => {
+                if self.max_size == 0 {
+                    panic!("`cache.max_size` must be greater than zero.");
+                }
+                if let Err(err) = std::fs::create_dir_all(&self.path) {
+                    panic!("Unable to create cache directory {}: {}", self.path, err);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Store {
+    pub path: String,
+    pub binaries_max_size: u64, // in bytes
+    pub testdata_max_size: u64, // in bytes
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self {
+            path: String::from(STORE_PATH),
+            binaries_max_size: STORE_BINARIES_MAX_SIZE,
+            testdata_max_size: STORE_TESTDATA_MAX_SIZE,
+        }
+    }
+}
+
+impl Store {
+    fn validate(&self) {
+        if self.binaries_max_size == 0 || self.testdata_max_size == 0 {
+            panic!("`store` sizes must be greater than zero.");
+        }
+        for dir in [STORE_BINARIES_PATH, STORE_TESTDATA_PATH] {
+            if let Err(err) = std::fs::create_dir_all(Path::new(&self.path).join(dir)) {
+                panic!("Unable to create store directory {}: {}", self.path, err);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Archive {
+    pub max_size: u64, // in bytes, after decompression
+    pub max_entries: usize,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self {
+            max_size: ARCHIVE_MAX_SIZE,
+            max_entries: ARCHIVE_MAX_ENTRIES,
+        }
+    }
+}
+
+impl Archive {
+    fn validate(&self) {
+        if self.max_size == 0 || self.max_entries == 0 {
+            panic!("`archive` limits must be greater than zero.");
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Heartbeat {
+    pub interval: u64, // in secs, between pings to the master
+    pub timeout: u64,  // in secs, reconnects when a ping is unanswered for this long
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: HEARTBEAT_INTERVAL,
+            timeout: HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
+impl Heartbeat {
+    fn validate(&self) {
+        if self.interval == 0 {
+            panic!("`heartbeat.interval` must be greater than zero.");
+        }
+        if self.timeout < self.interval {
+            panic!("`heartbeat.timeout` must not be less than `heartbeat.interval`.");
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Reconnect {
+    pub initial_delay: f64, // in secs, before the first retry
+    pub max_delay: f64,     // in secs
+    pub multiplier: f64,    // applied to the delay after every failed attempt
+    pub jitter: f64,        // up to this fraction of the delay is randomly cut off
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: RECONNECT_INITIAL_DELAY,
+            max_delay: RECONNECT_MAX_DELAY,
+            multiplier: RECONNECT_MULTIPLIER,
+            jitter: RECONNECT_JITTER,
+        }
+    }
+}
+
+impl Reconnect {
+    fn validate(&self) {
+        if self.initial_delay <= 0.0 {
+            panic!("`reconnect.initial_delay` must be greater than zero.");
+        }
+        if self.max_delay < self.initial_delay {
+            panic!("`reconnect.max_delay` must not be less than `reconnect.initial_delay`.");
+        }
+        if self.multiplier < 1.0 {
+            panic!("`reconnect.multiplier` must be at least 1.");
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            panic!("`reconnect.jitter` must be between 0 and 1.");
         }
     }
 }
@@ -32,11 +300,37 @@ impl Redis {
 pub struct Config {
     pub host: Host,
     pub redis: Redis,
+    #[serde(default)]
+    pub compile: Compile,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub store: Store,
+    #[serde(default)]
+    pub archive: Archive,
+    #[serde(default)]
+    pub heartbeat: Heartbeat,
+    #[serde(default)]
+    pub reconnect: Reconnect,
 }
 
 impl Config {
     pub fn validate(&self) {
         self.host.validate();
         self.redis.validate();
+        self.compile.validate();
+        self.cache.validate(&self.redis);
+        self.store.validate();
+        self.archive.validate();
+        self.heartbeat.validate();
+        self.reconnect.validate();
+    }
+
+    pub fn cache_backend(&self) -> CacheBackend {
+        match self.cache.backend {
+            Some(backend) => backend,
+            None if self.redis.enabled => CacheBackend::Redis,
+            None => CacheBackend::None,
+        }
     }
 }
\ No newline at end of file
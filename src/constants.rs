@@ -1,4 +1,3 @@
-pub const SLEEP_TIME: u64 = 5; // in secs
 pub const ISOLATE: &'static str = "isolate";
 pub const BASH: &'static str = "/bin/bash";
 pub const LOG_FILE_NAME: &'static str = "main.log";
@@ -17,9 +16,49 @@ pub const GRADERS_PATH: &'static str = "graders/";
 pub const LANGUAGES_PATH: &'static str = "langs/";
 pub const MAKE: &'static str = "make";
 pub const MAKE_ARGS: &'static str = "-j{threads}";
+pub const CMAKE: &'static str = "cmake";
+pub const CMAKE_BUILD_PATH: &'static str = "build/";
+pub const GRADERS_BUILD_FILE: &'static str = "build.toml";
+pub const GRADERS_MANIFEST_FILE: &'static str = "manifest.toml";
+pub const ARCHIVE_MAX_SIZE: u64 = 1073741824; // in bytes, after decompression
+pub const ARCHIVE_MAX_ENTRIES: usize = 10000;
 pub const CHECKER_TIME_LIMIT: f64 = 5.0;
 pub const CHECKER_MEM_LIMIT: u64 = 1048576;
+pub const COMPILE_TIME_LIMIT: f64 = 10.0;
+pub const COMPILE_WALL_TIME_LIMIT: f64 = 20.0;
+pub const COMPILE_MEM_LIMIT: u64 = 1048576;
+pub const COMPILE_PROCS: usize = 64;
+pub const COMPILE_FSIZE_LIMIT: u64 = 262144; // in kb
+pub const COMPILE_OUTPUT_LIMIT: usize = 65536; // in bytes, per stream
+pub const COMPILE_BOX_ID_BASE: u32 = 1; // box 0 is used for runs and checkers
+pub const COMPILE_BOX_COUNT: u32 = 16;
+pub const COMPILE_STDOUT_NAME: &'static str = ".compile.out";
+pub const COMPILE_STDERR_NAME: &'static str = ".compile.err";
+pub const SANDBOX_ROOT: &'static str = "/box";
 pub const CONVERT_TO_MILLISECS: f64 = 1000.0;
 pub const CONVERT_TO_SECONDS: f64 = 0.001;
-pub const CHECK_ALIVE_TIME: u64 = 5; // in secs
+pub const VERSION_PROBE_TIMEOUT: u64 = 10; // in secs
+pub const HEARTBEAT_INTERVAL: u64 = 5; // in secs
+pub const HEARTBEAT_TIMEOUT: u64 = 15; // in secs
+pub const RECONNECT_INITIAL_DELAY: f64 = 1.0; // in secs
+pub const RECONNECT_MAX_DELAY: f64 = 60.0; // in secs
+pub const RECONNECT_MULTIPLIER: f64 = 2.0;
+pub const RECONNECT_JITTER: f64 = 0.5;
+pub const LATENCY_SMOOTHING: f64 = 0.2; // Weight of the newest sample in the average
+pub const LATENCY_REPORT_INTERVAL: u64 = 60; // in pongs, between latency reports in the log
+pub const MAX_PARALLEL_RUNS: usize = 1; // a slave holds one judge at a time
+pub const CACHE_PATH: &'static str = "cache/";
+pub const CACHE_TEMP_PATH: &'static str = "tmp/";
+pub const CACHE_MAX_SIZE: u64 = 4294967296; // in bytes
+pub const CACHE_TTL: u64 = 604800; // in secs
+pub const CACHE_PURGE_BATCH: usize = 512;
+pub const CACHE_PREFIX: &'static str = "pms-slave:";
+pub const CACHE_TIMEOUT: u64 = 2000; // in ms
+pub const CACHE_RETRY_DELAY: u64 = 30; // in secs
+pub const STORE_PATH: &'static str = "store/";
+pub const STORE_BINARIES_PATH: &'static str = "bin/";
+pub const STORE_TESTDATA_PATH: &'static str = "testdata/";
+pub const STORE_TEMP_PATH: &'static str = "tmp/";
+pub const STORE_BINARIES_MAX_SIZE: u64 = 1073741824; // in bytes
+pub const STORE_TESTDATA_MAX_SIZE: u64 = 8589934592; // in bytes
 pub const LOG_CONFIG_FILE: &'static str = "log4rs.yaml";
@@ -1,16 +1,60 @@
 pub mod result;
 
+use super::archive::check_no_links;
 use super::constants::*;
 use super::language::Language;
 use super::CONFIG;
 use std::fs::{read_to_string, File};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
 use tempfile::{tempdir, TempDir};
 
 use result::ResultAppes;
 
+lazy_static! {
+    pub static ref SANDBOX: Box<dyn Sandbox> = sandbox();
+}
+
+#[cfg(not(test))]
+fn sandbox() -> Box<dyn Sandbox> {
+    Box::new(Isolate)
+}
+
+#[cfg(test)]
+fn sandbox() -> Box<dyn Sandbox> {
+    Box::new(crate::tests::sandbox::HostSandbox)
+}
+
+/// Runs the processes of a judge. Tests swap isolate for a fake.
+pub trait Sandbox: Send + Sync {
+    fn compile(&self, run: &CompileRun) -> CompileRunResult;
+    fn run(&self, run: &Run) -> RunResult;
+    fn run_v2(&self, run: &Runv2) -> RunResult;
+    fn check(&self, run: &CheckerRun) -> CheckerResult;
+}
+
+pub struct Isolate;
+
+impl Sandbox for Isolate {
+    fn compile(&self, run: &CompileRun) -> CompileRunResult {
+        run.run()
+    }
+
+    fn run(&self, run: &Run) -> RunResult {
+        run.run()
+    }
+
+    fn run_v2(&self, run: &Runv2) -> RunResult {
+        run.run()
+    }
+
+    fn check(&self, run: &CheckerRun) -> CheckerResult {
+        run.run()
+    }
+}
+
 #[derive(Debug)]
 pub struct CheckerRun {
     pub checker_lang: Language,
@@ -114,9 +158,27 @@ pub struct Runv2 {
     pub time_limit: f64,
     pub mem_limit: u64,
     pub procs: usize,
+    pub files: Vec<String>,
 }
 
 impl Runv2 {
+    /// Copies the grader `files` next to the running processes.
+    fn install_files(&self) -> Result<(), String> {
+        let graders_path = self.temp_path.join(GRADERS_PATH);
+        for file in self.files.iter() {
+            // The build may have replaced a listed file with a link
+            check_no_links(&graders_path, Path::new(file))?;
+            let dest = self.box_dir.path().join(file);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|err| format!("Unable to create directory of {}: {}", file, err))?;
+            }
+            std::fs::copy(graders_path.join(file), dest)
+                .map_err(|err| format!("Unable to copy {}: {}", file, err))?;
+        }
+        Ok(())
+    }
+
     pub fn run(&self) -> RunResult {
         // Clean up
         let _ = Command::new(ISOLATE)
@@ -132,6 +194,16 @@ impl Runv2 {
             .expect("Failed to run isolate command");
         // Run
         std::fs::copy(RUN_JUDGE_SH, self.box_dir.path().join(RUN_JUDGE_SH)).ok();
+        if let Err(err) = self.install_files() {
+            error!("(Runv2) {}", err);
+            return RunResult {
+                meta: RunMeta {
+                    status: Some(RunStatus::InternalErr),
+                    message: Some(err),
+                    ..Default::default()
+                },
+            };
+        }
         let exec_sh = self.box_dir.path().join(EXEC_SH);
         let exec_man_sh = self.box_dir.path().join(EXEC_MAN_SH);
         let mut exec_f = File::create(exec_sh.clone()).unwrap();
@@ -287,7 +359,7 @@ impl Run {
             .arg(&format!("--meta={}", log_p.clone().display(),))
             .arg(&format!("--dir=temp={}:rw", self.temp_path.display()))
             .arg(&format!("--dir=box={}", self.box_dir.path().display()))
-            .arg(
+            .args(
                 self.language
                     .parse_exec_cmd(PathBuf::from(&format!("/temp/{}", BINARY_NAME))),
             )
@@ -303,6 +375,134 @@ impl Run {
     }
 }
 
+static NEXT_COMPILE_BOX: AtomicU32 = AtomicU32::new(0);
+
+/// Compiles may run concurrently, so each one gets its own isolate box.
+pub fn compile_box_id() -> u32 {
+    COMPILE_BOX_ID_BASE + NEXT_COMPILE_BOX.fetch_add(1, Ordering::Relaxed) % COMPILE_BOX_COUNT
+}
+
+/// isolate does not search `PATH`, so resolve the executable on the host.
+/// Toolchains live under the directories mounted into the sandbox.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name)).filter(|p| p.is_file());
+    }
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file())
+    })
+}
+
+/// Makes `path` and everything below it writable by the sandbox user.
+pub fn share_with_sandbox(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if path.is_dir() {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o777)).ok();
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                share_with_sandbox(&entry.path());
+            }
+        }
+    } else if path.is_file() {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o777)).ok();
+    }
+}
+
+/// Reads at most `limit` bytes of `path`, marking how much was cut off.
+pub fn read_capped(path: &Path, limit: usize) -> Vec<u8> {
+    let mut buf = vec![];
+    if let Ok(f) = File::open(path) {
+        let len = f.metadata().map(|m| m.len()).unwrap_or(0);
+        f.take(limit as u64).read_to_end(&mut buf).ok();
+        if len > limit as u64 {
+            buf.extend_from_slice(
+                format!("\n[... {} bytes truncated]\n", len - limit as u64).as_bytes(),
+            );
+        }
+    }
+    buf
+}
+
+#[derive(Debug)]
+pub struct CompileRun {
+    pub box_id: u32,
+    pub work_dir: PathBuf, // Mounted as /box, commands run there
+    pub argv: Vec<String>,
+}
+
+impl CompileRun {
+    pub fn run(&self) -> CompileRunResult {
+        // Clean up
+        let _ = Command::new(ISOLATE)
+            .arg("--cg")
+            .arg(&format!("--box-id={}", self.box_id))
+            .arg("--cleanup")
+            .output()
+            .expect("Failed to run isolate command");
+        // Init sandbox
+        let _ = Command::new(ISOLATE)
+            .arg("--init")
+            .arg("--cg")
+            .arg(&format!("--box-id={}", self.box_id))
+            .output()
+            .expect("Failed to run isolate command");
+        // Run
+        let dir = tempdir().unwrap();
+        let log_p = dir.path().join(LOG_FILE_NAME);
+        share_with_sandbox(&self.work_dir);
+        let mut cmd = Command::new(ISOLATE);
+        cmd.arg("--run")
+            .arg("--cg")
+            .arg(&format!("--box-id={}", self.box_id))
+            .arg(&format!("-t {}", CONFIG.compile.time_limit))
+            .arg(&format!("-w {}", CONFIG.compile.wall_time_limit))
+            .arg(&format!("--cg-mem={}", CONFIG.compile.mem_limit))
+            .arg(&format!("-p {}", CONFIG.compile.procs))
+            .arg(&format!("--fsize={}", CONFIG.compile.fsize_limit))
+            .arg("-s")
+            .arg("--env=PATH")
+            .arg(&format!("--env=TMPDIR={}", SANDBOX_ROOT))
+            .arg(&format!(
+                "--stdout={}/{}",
+                SANDBOX_ROOT, COMPILE_STDOUT_NAME
+            ))
+            .arg(&format!(
+                "--stderr={}/{}",
+                SANDBOX_ROOT, COMPILE_STDERR_NAME
+            ))
+            .arg(&format!("--meta={}", log_p.clone().display()))
+            .arg(&format!("--dir=box={}:rw", self.work_dir.display()));
+        for extra in CONFIG.compile.dirs.iter() {
+            cmd.arg(&format!("--dir={}", extra));
+        }
+        let out = cmd
+            .arg("--")
+            .args(&self.argv)
+            .output()
+            .expect("Failed to run isolate command");
+        debug!("(Compile) stderr: {}", String::from_utf8_lossy(&out.stderr));
+        let (stdout_p, stderr_p) = (
+            self.work_dir.join(COMPILE_STDOUT_NAME),
+            self.work_dir.join(COMPILE_STDERR_NAME),
+        );
+        let stdout = read_capped(&stdout_p, CONFIG.compile.output_limit);
+        let stderr = read_capped(&stderr_p, CONFIG.compile.output_limit);
+        std::fs::remove_file(stdout_p).ok();
+        std::fs::remove_file(stderr_p).ok();
+        let meta = {
+            let s = read_to_string(log_p).expect("Failed to read a log file");
+            parse_meta(s).expect("Failed to parse a log file")
+        };
+        CompileRunResult {
+            meta,
+            stdout,
+            stderr,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum RunStatus {
     RuntimeErr,
@@ -312,7 +512,7 @@ pub enum RunStatus {
     Unknown,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct RunMeta {
     pub status: Option<RunStatus>,
     pub time: Option<f64>,
@@ -333,6 +533,13 @@ pub struct RunResult {
     pub meta: RunMeta,
 }
 
+#[derive(Clone, Debug)]
+pub struct CompileRunResult {
+    pub meta: RunMeta,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
 #[derive(Clone, Debug)]
 pub struct CheckerResult {
     pub score: Option<f64>,
@@ -0,0 +1,160 @@
+//! Parses compiler output into structured diagnostics for the master.
+
+use judge_protocol::judge::{Diagnostic, DiagnosticSeverity};
+use regex::Regex;
+use serde::Deserialize;
+
+/// `file:line:column: severity: message`, as printed by gcc and clang
+const GNU_PATTERN: &'static str = r"^(?P<file>[^:\n]+):(?P<line>\d+):(?P<column>\d+): (?P<severity>fatal error|error|warning|note): (?P<message>.*)$";
+
+/// How a language's compiler output is parsed, declared as `diagnostics` in its TOML.
+/// Patterns are matched against each line of the output.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub enum DiagnosticsFormat {
+    Json, // gcc with `-fdiagnostics-format=json`
+    Gnu,
+    Regex { pattern: String }, // Named groups `file`, `line`, `column`, `severity` and `message`
+}
+
+impl DiagnosticsFormat {
+    pub fn validate(&self) -> Result<(), String> {
+        if let DiagnosticsFormat::Regex { pattern } = self {
+            let regex = Regex::new(pattern)
+                .map_err(|err| format!("Invalid diagnostics pattern: {}", err))?;
+            for group in ["file", "line", "message"] {
+                if !regex.capture_names().flatten().any(|name| name == group) {
+                    return Err(format!("Diagnostics pattern has no `{}` group", group));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the diagnostics found in `output`, and `output` with JSON
+    /// diagnostics rendered as text so it stays readable.
+    pub fn parse(&self, output: &str) -> (String, Vec<Diagnostic>) {
+        match self {
+            DiagnosticsFormat::Json => parse_json(output),
+            DiagnosticsFormat::Gnu => (output.to_string(), parse_regex(GNU_PATTERN, output)),
+            DiagnosticsFormat::Regex { pattern } => {
+                (output.to_string(), parse_regex(pattern, output))
+            }
+        }
+    }
+}
+
+fn severity(s: &str) -> DiagnosticSeverity {
+    match s.to_ascii_lowercase().as_str() {
+        "error" | "fatal error" | "fatal" => DiagnosticSeverity::Error,
+        "warning" => DiagnosticSeverity::Warning,
+        _ => DiagnosticSeverity::Note,
+    }
+}
+
+fn render(diagnostic: &Diagnostic) -> String {
+    let severity = match diagnostic.severity {
+        DiagnosticSeverity::Error => "error",
+        DiagnosticSeverity::Warning => "warning",
+        DiagnosticSeverity::Note => "note",
+    };
+    format!(
+        "{}:{}:{}: {}: {}",
+        diagnostic.file, diagnostic.line, diagnostic.column, severity, diagnostic.message
+    )
+}
+
+fn parse_regex(pattern: &str, output: &str) -> Vec<Diagnostic> {
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(_) => return vec![],
+    };
+    output
+        .lines()
+        .filter_map(|line| {
+            let caps = regex.captures(line)?;
+            Some(Diagnostic {
+                file: caps.name("file")?.as_str().to_string(),
+                line: caps.name("line")?.as_str().parse().ok()?,
+                column: caps
+                    .name("column")
+                    .and_then(|column| column.as_str().parse().ok())
+                    .unwrap_or(0),
+                severity: caps
+                    .name("severity")
+                    .map(|s| severity(s.as_str()))
+                    .unwrap_or(DiagnosticSeverity::Error),
+                message: caps.name("message")?.as_str().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct GccCaret {
+    file: String,
+    line: u32,
+    column: u32,
+}
+
+#[derive(Deserialize)]
+struct GccLocation {
+    caret: GccCaret,
+}
+
+#[derive(Deserialize)]
+struct GccDiagnostic {
+    kind: String,
+    message: String,
+    #[serde(default)]
+    locations: Vec<GccLocation>,
+    #[serde(default)]
+    children: Vec<GccDiagnostic>,
+}
+
+fn flatten_gcc(diagnostic: GccDiagnostic, out: &mut Vec<Diagnostic>) {
+    let (file, line, column) = match diagnostic.locations.into_iter().next() {
+        Some(location) => (
+            location.caret.file,
+            location.caret.line,
+            location.caret.column,
+        ),
+        None => (String::new(), 0, 0),
+    };
+    out.push(Diagnostic {
+        file,
+        line,
+        column,
+        severity: severity(&diagnostic.kind),
+        message: diagnostic.message,
+    });
+    for child in diagnostic.children {
+        flatten_gcc(child, out);
+    }
+}
+
+/// gcc prints one JSON array per translation unit on its own line.
+/// Other lines, e.g. from `make`, are kept as they are.
+fn parse_json(output: &str) -> (String, Vec<Diagnostic>) {
+    let mut text = String::new();
+    let mut diagnostics = vec![];
+    for line in output.lines() {
+        match serde_json::from_str::<Vec<GccDiagnostic>>(line) {
+            Ok(parsed) => {
+                let start = diagnostics.len();
+                for diagnostic in parsed {
+                    flatten_gcc(diagnostic, &mut diagnostics);
+                }
+                for diagnostic in &diagnostics[start..] {
+                    text.push_str(&render(diagnostic));
+                    text.push('\n');
+                }
+            }
+            Err(_) => {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    (text, diagnostics)
+}
@@ -0,0 +1,257 @@
+//! Manifest and build description of grader archives.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+use crate::archive::check_no_links;
+use crate::constants::*;
+use crate::language::{Args, CompileStep, Language};
+
+/// Placeholders available to grader build arguments. Paths are as seen
+/// inside the sandbox, where the graders directory is `{workdir}`.
+#[derive(Serialize)]
+struct BuildCmd {
+    infile: PathBuf,
+    outfile: PathBuf,
+    workdir: PathBuf,
+    threads: usize,
+}
+
+/// How the graders directory is built, declared in `graders/build.toml`.
+/// Archives without it are built with `make`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum GraderBuild {
+    Make {
+        args: Option<Args>, // Defaults to `MAKE_ARGS`
+    },
+    Cmake {
+        #[serde(default)]
+        args: Args, // Passed when configuring, e.g. "-DCMAKE_BUILD_TYPE=Release"
+    },
+    Script {
+        path: String, // Relative to the graders directory, run with bash
+        #[serde(default)]
+        args: Args,
+    },
+    Steps {
+        #[serde(default)]
+        steps: Vec<CompileStep>, // The language's compile steps when empty
+    },
+}
+
+impl Default for GraderBuild {
+    fn default() -> Self {
+        GraderBuild::Make { args: None }
+    }
+}
+
+/// Whether `path` stays inside the directory it is relative to.
+pub fn is_contained(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+impl GraderBuild {
+    pub fn load(graders_path: &Path) -> Result<Self, String> {
+        let path = graders_path.join(GRADERS_BUILD_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let s = std::fs::read_to_string(&path)
+            .map_err(|err| format!("Unable to read {}: {}", GRADERS_BUILD_FILE, err))?;
+        toml::from_str(&s).map_err(|err| format!("Invalid {}: {}", GRADERS_BUILD_FILE, err))
+    }
+
+    pub fn validate(
+        &self,
+        lang: &Language,
+        code_rpath: &str,
+        object_rpath: &str,
+    ) -> Result<(), String> {
+        for path in [code_rpath, object_rpath] {
+            if !is_contained(path) {
+                return Err(format!("{} is outside of the graders", path));
+            }
+        }
+        if let GraderBuild::Script { path, .. } = self {
+            if !is_contained(path) {
+                return Err(format!("Build script {} is outside of the graders", path));
+            }
+        }
+        self.commands(lang, code_rpath, object_rpath).map(|_| ())
+    }
+
+    /// Every command of the build as `(executable, arguments)`, in order.
+    pub fn commands(
+        &self,
+        lang: &Language,
+        code_rpath: &str,
+        object_rpath: &str,
+    ) -> Result<Vec<(String, Vec<String>)>, String> {
+        let root = Path::new(SANDBOX_ROOT);
+        let build_dir = root.join(CMAKE_BUILD_PATH).display().to_string();
+        let cmd = BuildCmd {
+            infile: root.join(code_rpath),
+            outfile: root.join(object_rpath),
+            workdir: root.to_path_buf(),
+            threads: num_cpus::get(),
+        };
+        let commands = match self {
+            GraderBuild::Make { args } => {
+                let args = args
+                    .clone()
+                    .unwrap_or_else(|| Args::Line(String::from(MAKE_ARGS)));
+                vec![(String::from(MAKE), args.try_render(&cmd)?)]
+            }
+            GraderBuild::Cmake { args } => {
+                let mut configure = vec![
+                    String::from("-S"),
+                    root.display().to_string(),
+                    String::from("-B"),
+                    build_dir.clone(),
+                ];
+                configure.extend(args.try_render(&cmd)?);
+                let build = vec![
+                    String::from("--build"),
+                    build_dir,
+                    String::from("--parallel"),
+                    cmd.threads.to_string(),
+                ];
+                vec![
+                    (String::from(CMAKE), configure),
+                    (String::from(CMAKE), build),
+                ]
+            }
+            GraderBuild::Script { path, args } => {
+                let mut argv = vec![root.join(path).display().to_string()];
+                argv.extend(args.try_render(&cmd)?);
+                vec![(String::from(BASH), argv)]
+            }
+            GraderBuild::Steps { steps } => {
+                let steps = if steps.is_empty() {
+                    lang.pipeline()
+                } else {
+                    steps.clone()
+                };
+                if steps.is_empty() {
+                    return Err(format!("{} has no compile steps", lang.name));
+                }
+                steps
+                    .into_iter()
+                    .map(|step| Ok((step.exec, step.args.try_render(&cmd)?)))
+                    .collect::<Result<_, String>>()?
+            }
+        };
+        Ok(commands)
+    }
+}
+
+/// Limits for the processes of a judge, overriding the ones of the request.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub procs: Option<usize>,
+    pub time_limit: Option<u64>, // in ms
+    pub mem_limit: Option<u64>,  // in kb
+}
+
+/// Optional `manifest.toml` at the root of a grader archive.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub source: Option<String>, // Contestant source, relative to `graders/`
+    pub object: Option<String>, // Built object, relative to `graders/`
+    #[serde(default)]
+    pub files: Vec<String>, // Copied next to the running processes
+    #[serde(default)]
+    pub limits: Limits,
+    pub build: Option<GraderBuild>, // Takes precedence over `graders/build.toml`
+}
+
+/// A grader archive checked against its manifest, ready to be built.
+#[derive(Debug)]
+pub struct Graders {
+    pub source: String,
+    pub object: String,
+    pub files: Vec<String>,
+    pub limits: Limits,
+    pub build: GraderBuild,
+}
+
+impl Graders {
+    /// Writes the contestant source where the build expects it.
+    pub fn install_source(&self, graders_path: &Path, code: &[u8]) -> Result<(), String> {
+        check_no_links(graders_path, Path::new(&self.source))?;
+        std::fs::write(graders_path.join(&self.source), code)
+            .map_err(|err| format!("Unable to write {}: {}", self.source, err))
+    }
+
+    /// Reads the archive unpacked at `root`. Paths missing from the manifest
+    /// are taken from the request.
+    pub fn load(
+        root: &Path,
+        lang: &Language,
+        main_path: String,
+        object_path: String,
+    ) -> Result<Self, String> {
+        let path = root.join(GRADERS_MANIFEST_FILE);
+        let manifest = if path.exists() {
+            let s = std::fs::read_to_string(&path)
+                .map_err(|err| format!("Unable to read {}: {}", GRADERS_MANIFEST_FILE, err))?;
+            toml::from_str::<Manifest>(&s)
+                .map_err(|err| format!("Invalid {}: {}", GRADERS_MANIFEST_FILE, err))?
+        } else {
+            Manifest::default()
+        };
+        let graders_path = root.join(GRADERS_PATH);
+        let non_empty = |s: String| Some(s).filter(|s| !s.is_empty());
+        let source = manifest
+            .source
+            .or_else(|| non_empty(main_path))
+            .ok_or("Neither the manifest nor the request names the contestant source")?;
+        let object = manifest
+            .object
+            .or_else(|| non_empty(object_path))
+            .ok_or("Neither the manifest nor the request names the output object")?;
+        for file in manifest.files.iter() {
+            if !is_contained(file) {
+                return Err(format!("{} is outside of the graders", file));
+            }
+            check_no_links(&graders_path, Path::new(file))?;
+            if !graders_path.join(file).is_file() {
+                return Err(format!(
+                    "{} listed in {} does not exist",
+                    file, GRADERS_MANIFEST_FILE
+                ));
+            }
+        }
+        let limits = manifest.limits;
+        if limits.procs == Some(0) || limits.time_limit == Some(0) || limits.mem_limit == Some(0) {
+            return Err(format!(
+                "Limits in {} must be greater than zero",
+                GRADERS_MANIFEST_FILE
+            ));
+        }
+        let build = match manifest.build {
+            Some(build) => build,
+            None => GraderBuild::load(&graders_path)?,
+        };
+        build.validate(lang, &source, &object)?;
+        for path in [&source, &object] {
+            check_no_links(&graders_path, Path::new(path))?;
+        }
+        if let Some(parent) = graders_path.join(&source).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("Unable to create directory of {}: {}", source, err))?;
+        }
+        Ok(Self {
+            source,
+            object,
+            files: manifest.files,
+            limits,
+            build,
+        })
+    }
+}
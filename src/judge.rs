@@ -2,11 +2,14 @@ use async_std::net::TcpStream;
 use bincode::Options;
 use judge_protocol::judge::*;
 use judge_protocol::packet::*;
+use std::io::prelude::*;
 use std::path::PathBuf;
 use std::pin::Pin;
 use tempfile::TempDir;
 use uuid::Uuid;
 
+use crate::constants::*;
+use crate::container::*;
 use crate::language::Language;
 
 pub struct OnJudge {
@@ -17,8 +20,95 @@ pub struct OnJudge {
     pub main_binary: PathBuf,
     pub checker_binary: PathBuf,
     pub object_binary: Option<String>,
+    pub grader_files: Vec<String>, // Relative to `graders/`, copied into the box of every run
     pub time_limit: u64, // in ms
     pub mem_limit: u64,  // in kb
     pub tempdir: TempDir,
     pub procs: usize,
 }
+
+impl OnJudge {
+    /// Runs the installed test against the solution and checks its output.
+    /// Judges with a manager run in 'novel' mode, others in 'simple' mode.
+    pub fn run_test(&self, test_uuid: Uuid) -> JudgeState {
+        let stdout_p = self.tempdir.path().join(STDOUT_FILE_NAME);
+        let mut stdout_f = std::fs::File::create(stdout_p.clone()).unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        stdout_f.flush().ok();
+        std::fs::set_permissions(
+            self.tempdir.path().to_path_buf(),
+            std::fs::Permissions::from_mode(0o777),
+        )
+        .ok();
+        std::fs::set_permissions(stdout_p, std::fs::Permissions::from_mode(0o777)).ok();
+        let run_tempdir = tempfile::tempdir().unwrap();
+        let time_limit = (self.time_limit as f64) * CONVERT_TO_SECONDS;
+        let res = if let (Some(manager_lang), Some(object_path)) =
+            (self.manager_lang.clone(), self.object_binary.clone())
+        {
+            // 'Novel' mode
+            SANDBOX.run_v2(&Runv2 {
+                temp_path: self.tempdir.path().to_path_buf(),
+                object_path,
+                box_dir: run_tempdir,
+                main_lang: self.main_lang.clone(),
+                manager_lang,
+                time_limit,
+                mem_limit: self.mem_limit,
+                procs: self.procs,
+                files: self.grader_files.clone(),
+            })
+        } else {
+            // 'Simple' mode
+            SANDBOX.run(&Run {
+                temp_path: self.tempdir.path().to_path_buf(),
+                box_dir: run_tempdir,
+                language: self.main_lang.clone(),
+                time_limit,
+                mem_limit: self.mem_limit,
+            })
+        };
+        debug!("(Judge: {}) (Test: {}) {:?}", self.uuid, test_uuid, res.meta);
+        if let Some(state) = run_verdict(test_uuid, &res.meta) {
+            return state;
+        }
+        // Let's check stdout by checker
+        let checker = CheckerRun {
+            checker_lang: self.checker_lang.clone(),
+            temp_path: self.tempdir.path().to_path_buf(),
+            box_dir: tempfile::tempdir().unwrap(),
+        };
+        let res_checker = SANDBOX.check(&checker);
+        debug!(
+            "(Checker) (Judge: {}) (Test: {}) {:?}",
+            self.uuid, test_uuid, res_checker.meta
+        );
+        checker_verdict(test_uuid, &res_checker)
+    }
+}
+
+/// The verdict of a run that failed, or `None` when its output is to be checked.
+pub fn run_verdict(test_uuid: Uuid, meta: &RunMeta) -> Option<JudgeState> {
+    let state = match meta.status? {
+        RunStatus::TimedOut => JudgeState::TimeLimitExceed(test_uuid),
+        RunStatus::DiedOnSignal => JudgeState::DiedOnSignal(test_uuid, meta.exitsig.unwrap_or(0)),
+        RunStatus::InternalErr => JudgeState::InternalError(test_uuid),
+        RunStatus::RuntimeErr => JudgeState::RuntimeError(test_uuid, meta.exitcode.unwrap_or(0)),
+        RunStatus::Unknown => JudgeState::UnknownError,
+    };
+    Some(state)
+}
+
+/// The verdict of a checked run. A checker that fails rejects the output,
+/// and one that prints a score gives partial points.
+pub fn checker_verdict(test_uuid: Uuid, res: &CheckerResult) -> JudgeState {
+    let time = (res.meta.time.unwrap_or(0.0) * CONVERT_TO_MILLISECS) as u64;
+    let mem = res.meta.cg_mem.unwrap_or(0);
+    if res.meta.status.is_some() {
+        JudgeState::WrongAnswer(test_uuid, time, mem)
+    } else if let Some(score) = res.score {
+        JudgeState::Complete(test_uuid, score, time, mem)
+    } else {
+        JudgeState::Accepted(test_uuid, time, mem)
+    }
+}
@@ -5,31 +5,100 @@ use std::collections::HashMap;
 use std::fs::{read_dir, read_to_string};
 use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
 use async_std::fs::File;
+use async_std::task;
 use async_std::io::prelude::*;
 
 use tempfile::NamedTempFile;
 
 use tinytemplate::TinyTemplate;
 
-use redis::{Client, AsyncCommands};
-
+use crate::cache;
+use crate::capability::{DisabledLanguage, LanguageInfo};
 use crate::constants::*;
-use crate::CONFIG;
+use crate::diagnostics::DiagnosticsFormat;
+use crate::graders::GraderBuild;
+use crate::container::{
+    compile_box_id, find_executable, CompileRun, CompileRunResult, RunStatus, SANDBOX,
+};
+use crate::store;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Language {
     pub uuid: Uuid,
     pub name: String, // Display name
     pub version: String,
-    pub exec_cmd: String,
-    pub compile_exec: String,
-    pub compile_args: String,
+    pub exec_cmd: Args,
+    pub compile_exec: Option<String>, // None for interpreted languages
+    pub compile_args: Option<Args>,
+    #[serde(default)]
+    pub compile_steps: Vec<CompileStep>, // Runs in order, after `compile_exec` if both are given
+    pub check_exec: Option<String>, // Syntax/bytecode check when there is no compile step
+    pub check_args: Option<Args>,
     pub entry_source: String,
     pub add_mem_limit: u64,
     pub add_time_limit: u64,
+    pub version_probe: Option<Args>, // e.g. "g++ --version"
+    pub diagnostics: Option<DiagnosticsFormat>,
+    #[serde(skip)]
+    pub toolchain: Option<String>, // Detected by `version_probe`
+    #[serde(skip)]
+    pub fingerprint: String, // Toolchain version and executables, see `toolchain_fingerprint`
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompileStep {
+    pub exec: String,
+    #[serde(default)]
+    pub args: Args,
+}
+
+/// Command line arguments of a language, templated per argument.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Args {
+    Line(String),      // Split like a shell would, e.g. "-O2 '{infile}'"
+    Argv(Vec<String>), // Taken as is, e.g. ["-DNAME=a b", "{infile}"]
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args::Line(String::new())
+    }
+}
+
+impl Args {
+    pub fn words(&self) -> Result<Vec<String>, String> {
+        match self {
+            Args::Line(line) => shell_words::split(line)
+                .map_err(|err| format!("Unable to split `{}`: {}", line, err)),
+            Args::Argv(argv) => Ok(argv.clone()),
+        }
+    }
+
+    /// Splits first and renders every word on its own, so substituted
+    /// paths containing spaces stay a single argument.
+    pub fn render<C: Serialize>(&self, context: &C) -> Vec<String> {
+        self.try_render(context)
+            .expect("Arguments must be validated on load")
+    }
+
+    /// Same as `render`, for arguments that come from untrusted archives.
+    pub fn try_render<C: Serialize>(&self, context: &C) -> Result<Vec<String>, String> {
+        self.words()?
+            .iter()
+            .map(|word| {
+                let mut tt = TinyTemplate::new();
+                tt.set_default_formatter(&tinytemplate::format_unescaped);
+                tt.add_template("arg", word)
+                    .and_then(|_| tt.render("arg", context))
+                    .map_err(|err| format!("Unable to render `{}`: {}", word, err))
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize)]
@@ -46,145 +115,597 @@ pub struct ExecSh {
 pub struct CompileCmd {
     infile: PathBuf,
     outfile: PathBuf,
+    workdir: PathBuf,
 }
 
-#[derive(Serialize)]
-pub struct MakeCmd {
-    threads: usize,
+/// Compiler output, decoded lossily since compilers may print non-UTF-8 bytes
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CompileOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CompileOutput {
+    /// Paths under the sandbox root or `work_dir` are made relative,
+    /// so they read as the names the contestant's files were given.
+    pub fn from_run(res: &CompileRunResult, work_dir: &Path) -> Self {
+        let roots = [
+            format!("{}/", work_dir.display()),
+            format!("{}/", SANDBOX_ROOT),
+        ];
+        let rewrite = |bytes: &[u8]| {
+            roots
+                .iter()
+                .fold(String::from_utf8_lossy(bytes).into_owned(), |text, root| {
+                    text.replace(root.as_str(), "")
+                })
+        };
+        Self {
+            stdout: rewrite(&res.stdout),
+            stderr: rewrite(&res.stderr),
+        }
+    }
+
+    fn append(&mut self, other: CompileOutput) {
+        self.stdout.push_str(&other.stdout);
+        self.stderr.push_str(&other.stderr);
+    }
+
+    /// Both streams as one message, e.g. warnings printed on a successful compile.
+    pub fn message(&self) -> String {
+        match (self.stdout.is_empty(), self.stderr.is_empty()) {
+            (_, true) => self.stdout.clone(),
+            (true, false) => self.stderr.clone(),
+            (false, false) => format!("{}\n{}", self.stdout.trim_end(), self.stderr),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CompileResult {
-    Success(String),
+    Success(CompileOutput),
     Error(String),
+    TimeLimitExceed(String),
+    Aborted(String), // The sandbox or this node failed, which says nothing about the code
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct CacheGraderCompile {
+pub struct CacheCompile {
     result: CompileResult,
     raw: Vec<u8>,
 }
 
-pub fn parse_make_args() -> String {
-    let mut tt = TinyTemplate::new();
-    tt.add_template("make", MAKE_ARGS).ok();
-    let make = MakeCmd {
-        threads: num_cpus::get(),
-    };
-    tt.render("make", &make).unwrap()
+/// Compiles on the blocking pool, so a long compile doesn't hold up the
+/// executor that answers pings.
+async fn sandbox_compile(run: CompileRun) -> CompileRunResult {
+    task::spawn_blocking(move || SANDBOX.compile(&run)).await
+}
+
+fn compile_result(res: CompileRunResult, work_dir: &Path) -> CompileResult {
+    let output = CompileOutput::from_run(&res, work_dir);
+    match res.meta.status {
+        None => CompileResult::Success(output),
+        Some(RunStatus::RuntimeErr) => CompileResult::Error(output.message()),
+        Some(RunStatus::TimedOut) => CompileResult::TimeLimitExceed(output.message()),
+        Some(status) => {
+            let mut message = output.message();
+            message.push_str(&format!(
+                "\nCompilation was aborted by the sandbox: {:?}",
+                status
+            ));
+            if let Some(reason) = res.meta.message {
+                message.push_str(&format!(" ({})", reason));
+            }
+            CompileResult::Aborted(message)
+        }
+    }
+}
+
+/// Identifies the installed executable `name` by its path, size and mtime,
+/// so that upgrading a compiler changes every cache key built with it.
+pub fn executable_fingerprint(name: &str) -> String {
+    find_executable(name)
+        .and_then(|path| {
+            let meta = std::fs::metadata(&path).ok()?;
+            let mtime = meta
+                .modified()
+                .ok()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()?;
+            Some(format!(
+                "{}:{}:{}",
+                path.display(),
+                meta.len(),
+                mtime.as_secs()
+            ))
+        })
+        .unwrap_or_else(|| format!("{}:missing", name))
 }
 
 pub async fn compile_with_graders(
+    lang: &Language,
+    build: &GraderBuild,
     grader_hash: &[u8; 32],
     grader_path: PathBuf,
     code: Vec<u8>,
     object_rpath: String,
     code_rpath: String,
 ) -> CompileResult {
-    if CONFIG.redis.enabled {
-        let code_hash = *blake3::hash(&code).as_bytes();
-        let client = redis::Client::open(CONFIG.redis.redis.as_ref().unwrap().clone()).expect(&format!("Cannot make connection to redis server: {}", CONFIG.redis.redis.as_ref().unwrap().clone()));
-        let mut con = client.get_async_connection().await.expect(&format!("Cannot establish connection to redis server: {}", CONFIG.redis.redis.as_ref().unwrap().clone()));
-        if let Ok(cache) = con.get(&[&grader_hash, &code_hash]).await {
-            let cache: Vec<u8> = cache;
-            if let Ok(cache_object) = bson::from_slice::<CacheGraderCompile>(&cache) {
-                let mut f = File::create(grader_path.clone().join(object_rpath)).await.unwrap();
-                f.write_all(&cache_object.raw).await.unwrap();
-                f.sync_all().await.unwrap();
+    let commands = match build.commands(lang, &code_rpath, &object_rpath) {
+        Ok(commands) => commands,
+        Err(err) => return CompileResult::Error(err),
+    };
+    let code_hash = *blake3::hash(&code).as_bytes();
+    // Thread counts do not change the build, so the declaration is used
+    // instead of the rendered commands to share entries between nodes.
+    let executables = commands
+        .iter()
+        .map(|(exec, _)| executable_fingerprint(exec))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let key = cache::make_key(
+        "graders",
+        &[
+            lang.uuid.as_bytes(),
+            lang.fingerprint.as_bytes(),
+            &lang.rendered_pipeline(Path::new(&object_rpath)),
+            format!("{:?}", build).as_bytes(),
+            executables.as_bytes(),
+            object_rpath.as_bytes(),
+            code_rpath.as_bytes(),
+            grader_hash,
+            &code_hash,
+        ],
+    );
+    if cache::enabled() {
+        if let Some(cache) = cache::get(&key).await {
+            if let Ok(cache_object) = bson::from_slice::<CacheCompile>(&cache) {
+                if let CompileResult::Success(_) = cache_object.result {
+                    let object_path = grader_path.join(&object_rpath);
+                    if let Err(err) = restore_compile(&object_path, &cache_object.raw).await {
+                        return CompileResult::Aborted(format!(
+                            "Unable to restore cached {}: {}",
+                            object_rpath, err
+                        ));
+                    }
+                }
                 return cache_object.result;
             } else {
                 warn!("Maybe cache is corrupted?");
             }
         }
     }
-    // TODO: make it works as asynchronous
-    let path = grader_path.clone().join(code_rpath);
-    let mut tempfile = File::create(path.clone()).await.unwrap();
-    tempfile.write_all(&code).await.unwrap();
-    tempfile.sync_all().await.unwrap();
-    let cmd = Command::new(MAKE)
-        .current_dir(grader_path.clone())
-        .args(parse_make_args().split_whitespace())
-        .output()
-        .expect("Failed to compile");
-    let res = if cmd.status.success() {
-        CompileResult::Success(String::from_utf8(cmd.stdout).unwrap())
-    } else {
-        CompileResult::Error(String::from_utf8(cmd.stderr).unwrap())
-    };
-    if CONFIG.redis.enabled {
-        let object_data: Vec<u8> = {
-            if let Ok(mut object_f) = File::open(grader_path.join(object_rpath)).await {
-                let mut buf = vec![];
-                object_f.read_to_end(&mut buf).await.unwrap();
-                buf
-            } else {
-                vec![]
+    let mut output = CompileOutput::default();
+    let mut res = None;
+    for (exec, args) in commands {
+        let exec_path = match find_executable(&exec) {
+            Some(exec_path) => exec_path,
+            None => {
+                res = Some(CompileResult::Aborted(format!("Unable to find {}", exec)));
+                break;
             }
         };
-        let cache = CacheGraderCompile {
-            result: res.clone(),
-            raw: object_data,
+        let mut argv = vec![exec_path.display().to_string()];
+        argv.extend(args);
+        let run = CompileRun {
+            box_id: compile_box_id(),
+            work_dir: grader_path.clone(),
+            argv,
         };
-        let code_hash: [u8;32] = *blake3::hash(&code).as_bytes();
-        let client = redis::Client::open(CONFIG.redis.redis.as_ref().unwrap().clone()).expect(&format!("Cannot make connection to redis server: {}", CONFIG.redis.redis.as_ref().unwrap().clone()));
-        let mut con = client.get_async_connection().await.expect(&format!("Cannot establish connection to redis server: {}", CONFIG.redis.redis.as_ref().unwrap().clone()));
-        let _: () = con.set(&[&grader_hash, &code_hash], bson::to_vec(&cache).unwrap()).await.unwrap();
+        match compile_result(sandbox_compile(run).await, &grader_path) {
+            CompileResult::Success(out) => output.append(out),
+            err => {
+                res = Some(err);
+                break;
+            }
+        }
+    }
+    let res = res.unwrap_or(CompileResult::Success(output));
+    if cache::enabled() {
+        store_compile(&key, &res, grader_path.join(object_rpath)).await;
     }
     res
 }
 
+/// Writes a cached artifact back to `path` as an executable, creating the
+/// directories a nested object needs, e.g. `build/` of cmake graders.
+async fn restore_compile(path: &Path, raw: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(parent) = path.parent() {
+        async_std::fs::create_dir_all(parent).await?;
+    }
+    let mut f = File::create(path).await?;
+    f.write_all(raw).await?;
+    f.sync_all().await?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+}
+
+/// Stores `res` together with the artifact at `object_path`.
+async fn store_compile(key: &str, res: &CompileResult, object_path: PathBuf) {
+    let object_data = match res {
+        // Without its artifact a success can't be replayed
+        CompileResult::Success(_) => match async_std::fs::read(object_path).await {
+            Ok(buf) => buf,
+            Err(_) => return,
+        },
+        CompileResult::Error(_) => vec![],
+        // Timeouts depend on the load of this node, and aborts on its sandbox
+        CompileResult::TimeLimitExceed(_) | CompileResult::Aborted(_) => return,
+    };
+    let cache = CacheCompile {
+        result: res.clone(),
+        raw: object_data,
+    };
+    cache::set(key, bson::to_vec(&cache).unwrap()).await;
+}
+
 impl Language {
-    pub fn parse_exec_cmd(&self, binary_path: PathBuf) -> String {
-        let mut tt = TinyTemplate::new();
-        tt.add_template("exec", &self.exec_cmd).ok();
+    pub fn info(&self) -> LanguageInfo {
+        LanguageInfo {
+            uuid: self.uuid,
+            name: self.name.clone(),
+            version: self.version.clone(),
+            toolchain: self.toolchain.clone(),
+        }
+    }
+
+    /// Runs `version_probe` and returns the first line it printed,
+    /// or the reason why the toolchain is considered missing.
+    pub fn probe_toolchain(&self) -> Result<Option<String>, String> {
+        self.probe_toolchain_within(Duration::from_secs(VERSION_PROBE_TIMEOUT))
+    }
+
+    /// `probe_toolchain`, killing the probe after `timeout`.
+    pub fn probe_toolchain_within(&self, timeout: Duration) -> Result<Option<String>, String> {
+        let probe = match &self.version_probe {
+            Some(probe) => probe,
+            None => return Ok(None),
+        };
+        let argv = probe.words()?;
+        let (exec, args) = argv
+            .split_first()
+            .ok_or_else(|| String::from("`version_probe` is empty"))?;
+        let run_err = |err: io::Error| format!("Failed to run `{}`: {}", argv.join(" "), err);
+        // Output goes to files, so a chatty probe can't block on a full pipe
+        let (mut stdout_f, mut stderr_f) = (
+            tempfile::tempfile().map_err(run_err)?,
+            tempfile::tempfile().map_err(run_err)?,
+        );
+        let mut child = Command::new(exec)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(stdout_f.try_clone().map_err(run_err)?)
+            .stderr(stderr_f.try_clone().map_err(run_err)?)
+            .spawn()
+            .map_err(run_err)?;
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(run_err)? {
+                break status;
+            }
+            if Instant::now() > deadline {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!(
+                    "`{}` did not finish in {:?}",
+                    argv.join(" "),
+                    timeout
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Err(format!("`{}` exited with {}", argv.join(" "), status));
+        }
+        let (mut stdout, mut stderr) = (String::new(), String::new());
+        for (f, out) in [(&mut stdout_f, &mut stdout), (&mut stderr_f, &mut stderr)] {
+            io::Seek::seek(f, io::SeekFrom::Start(0)).map_err(run_err)?;
+            let mut buf = vec![];
+            io::Read::read_to_end(f, &mut buf).map_err(run_err)?;
+            *out = String::from_utf8_lossy(&buf).into_owned();
+        }
+        Ok(stdout
+            .lines()
+            .chain(stderr.lines())
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(String::from))
+    }
+
+    /// Checks that every templated command line can be split and rendered,
+    /// so unknown placeholders fail on load instead of on the first judge.
+    pub fn validate(&self) -> Result<(), String> {
+        self.exec_cmd.try_render(&ExecCmd {
+            file: PathBuf::from(BINARY_NAME),
+        })?;
+        let compile = CompileCmd {
+            infile: PathBuf::from(&self.entry_source),
+            outfile: PathBuf::from(BINARY_NAME),
+            workdir: PathBuf::from(SANDBOX_ROOT),
+        };
+        for args in [&self.compile_args, &self.check_args].into_iter().flatten() {
+            args.try_render(&compile)?;
+        }
+        for step in self.compile_steps.iter() {
+            step.args.try_render(&compile)?;
+        }
+        if let Some(probe) = &self.version_probe {
+            probe.words()?;
+        }
+        if let Some(diagnostics) = &self.diagnostics {
+            diagnostics.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_exec_cmd(&self, binary_path: PathBuf) -> Vec<String> {
         let exec = ExecCmd { file: binary_path };
-        tt.render("exec", &exec).unwrap()
+        self.exec_cmd.render(&exec)
     }
 
     pub fn parse_exec_sh(&self, binary_path: PathBuf) -> String {
         let mut tt = TinyTemplate::new();
+        tt.set_default_formatter(&tinytemplate::format_unescaped);
         tt.add_template("sh", include_str!("../assets/scripts/exec.template.sh"))
             .ok();
         let sh = ExecSh {
-            language_command: self.parse_exec_cmd(binary_path),
+            language_command: shell_words::join(self.parse_exec_cmd(binary_path)),
         };
         tt.render("sh", &sh).unwrap()
     }
 
-    pub fn parse_compile_args(&self, infile: PathBuf, outfile: PathBuf) -> String {
-        let mut tt = TinyTemplate::new();
-        tt.add_template("compile", &self.compile_args).ok();
-        let compile = CompileCmd { infile, outfile };
-        tt.render("compile", &compile).unwrap()
+    pub fn parse_compile_args(
+        &self,
+        args: &Args,
+        infile: PathBuf,
+        outfile: PathBuf,
+        workdir: PathBuf,
+    ) -> Vec<String> {
+        let compile = CompileCmd {
+            infile,
+            outfile,
+            workdir,
+        };
+        args.render(&compile)
+    }
+
+    /// `compile_exec` (if any) followed by `compile_steps`.
+    pub fn pipeline(&self) -> Vec<CompileStep> {
+        let mut steps = vec![];
+        if let Some(compile_exec) = &self.compile_exec {
+            steps.push(CompileStep {
+                exec: compile_exec.clone(),
+                args: self.compile_args.clone().unwrap_or_default(),
+            });
+        }
+        steps.extend(self.compile_steps.iter().cloned());
+        steps
+    }
+
+    async fn run_compile_step(
+        &self,
+        step: &CompileStep,
+        infile: PathBuf,
+        outfile: PathBuf,
+        workdir: &Path,
+    ) -> CompileResult {
+        let exec = match find_executable(&step.exec) {
+            Some(exec) => exec,
+            None => return CompileResult::Aborted(format!("Unable to find {}", step.exec)),
+        };
+        let mut argv = vec![exec.display().to_string()];
+        argv.extend(self.parse_compile_args(
+            &step.args,
+            infile,
+            outfile,
+            PathBuf::from(SANDBOX_ROOT),
+        ));
+        let run = CompileRun {
+            box_id: compile_box_id(),
+            work_dir: workdir.to_path_buf(),
+            argv,
+        };
+        compile_result(sandbox_compile(run).await, workdir)
+    }
+
+    pub fn toolchain_fingerprint(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.toolchain.clone().unwrap_or_default().as_bytes());
+        for exec in self
+            .pipeline()
+            .into_iter()
+            .map(|step| step.exec)
+            .chain(self.check_exec.clone())
+        {
+            hasher.update(b"\0");
+            hasher.update(executable_fingerprint(&exec).as_bytes());
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Every compile command as it would run in the sandbox.
+    pub fn rendered_pipeline(&self, outfile: &Path) -> Vec<u8> {
+        let root = Path::new(SANDBOX_ROOT);
+        let (infile, outfile) = (
+            root.join(self.entry_source.clone()),
+            root.join(outfile.file_name().unwrap_or_default()),
+        );
+        let mut steps = self.pipeline();
+        if let Some(check_exec) = &self.check_exec {
+            steps.push(CompileStep {
+                exec: check_exec.clone(),
+                args: self.check_args.clone().unwrap_or_default(),
+            });
+        }
+        let mut rendered = vec![];
+        for step in steps.iter() {
+            rendered.extend_from_slice(step.exec.as_bytes());
+            for arg in self.parse_compile_args(
+                &step.args,
+                infile.clone(),
+                outfile.clone(),
+                root.to_path_buf(),
+            ) {
+                rendered.push(0);
+                rendered.extend_from_slice(arg.as_bytes());
+            }
+            rendered.push(b'\n');
+        }
+        rendered
+    }
+
+    pub fn cache_key(&self, code: &[u8], outfile: &Path) -> String {
+        let code_hash = *blake3::hash(code).as_bytes();
+        cache::make_key(
+            "compile",
+            &[
+                self.uuid.as_bytes(),
+                self.fingerprint.as_bytes(),
+                &self.rendered_pipeline(outfile),
+                self.entry_source.as_bytes(),
+                &code_hash,
+            ],
+        )
     }
 
     pub async fn compile(&self, code: Vec<u8>, outfile: PathBuf) -> CompileResult {
+        let key = self.cache_key(&code, &outfile);
+        if cache::enabled() {
+            if let Some(cache) = cache::get(&key).await {
+                if let Ok(cache_object) = bson::from_slice::<CacheCompile>(&cache) {
+                    if let CompileResult::Success(_) = cache_object.result {
+                        if let Err(err) = restore_compile(&outfile, &cache_object.raw).await {
+                            return CompileResult::Aborted(format!(
+                                "Unable to restore cached binary: {}",
+                                err
+                            ));
+                        }
+                    }
+                    return cache_object.result;
+                } else {
+                    warn!("Maybe cache is corrupted?");
+                }
+            }
+        }
+        let res = self.compile_uncached(code, outfile.clone()).await;
+        if cache::enabled() {
+            store_compile(&key, &res, outfile).await;
+        }
+        res
+    }
+
+    /// Name of the stored binary built from the source with blake3 hex `hash`.
+    pub fn store_name(&self, hash: &str, outfile: &Path) -> String {
+        cache::hash_parts(&[
+            self.uuid.as_bytes(),
+            self.fingerprint.as_bytes(),
+            &self.rendered_pipeline(outfile),
+            hash.as_bytes(),
+        ])
+        .to_hex()
+        .to_string()
+    }
+
+    /// Installs the binary stored for `hash` into `outfile`. On a miss `code` is
+    /// compiled instead and the binary is kept for the next judge.
+    /// Without a `hash` this is the same as `compile`.
+    pub async fn compile_stored(
+        &self,
+        hash: Option<&str>,
+        code: Vec<u8>,
+        outfile: PathBuf,
+    ) -> CompileResult {
+        let hash = match hash {
+            Some(hash) => hash,
+            None => return self.compile(code, outfile).await,
+        };
+        let name = self.store_name(hash, &outfile);
+        if let Some(stored) = store::BINARIES.get(&name) {
+            use std::os::unix::fs::PermissionsExt;
+            if std::fs::copy(&stored, &outfile).is_ok() {
+                std::fs::set_permissions(&outfile, std::fs::Permissions::from_mode(0o755)).ok();
+                return CompileResult::Success(CompileOutput::default());
+            }
+        }
+        if code.is_empty() {
+            return CompileResult::Error(format!("{} is not in the binary store", hash));
+        }
+        if blake3::hash(&code).to_hex().as_str() != hash {
+            return CompileResult::Error(format!("Source does not match its hash {}", hash));
+        }
+        let res = self.compile(code, outfile.clone()).await;
+        if let CompileResult::Success(_) = res {
+            if let Err(err) = store::BINARIES.insert_file(&name, &outfile) {
+                warn!("Unable to store binary {}: {}", hash, err);
+            }
+        }
+        res
+    }
+
+    async fn compile_uncached(&self, code: Vec<u8>, outfile: PathBuf) -> CompileResult {
         // TODO: make it works as asynchronous
         let dir = tempfile::tempdir().unwrap();
         let path = dir.path().join(self.entry_source.clone());
         let mut tempfile = std::fs::File::create(path.clone()).unwrap();
         tempfile.write_all(&code).ok();
         tempfile.flush().ok();
-        let cmd = Command::new(&self.compile_exec)
-            .args(
-                self.parse_compile_args(path.to_path_buf(), outfile.clone())
-                    .split_whitespace(),
-            )
-            .output()
-            .expect("Failed to compile");
         trace!("{:?}", outfile.clone());
-        if cmd.status.success() {
-            CompileResult::Success(String::from_utf8(cmd.stdout).unwrap())
+        // Steps run inside the sandbox where `dir` is mounted as /box
+        let out_name = outfile.file_name().unwrap().to_os_string();
+        let (infile, sandbox_outfile) = (
+            Path::new(SANDBOX_ROOT).join(self.entry_source.clone()),
+            Path::new(SANDBOX_ROOT).join(out_name.clone()),
+        );
+        let pipeline = self.pipeline();
+        if !pipeline.is_empty() {
+            // Every step shares `workdir`, so later steps can consume
+            // the artifacts of earlier ones.
+            let mut output = CompileOutput::default();
+            for step in pipeline.iter() {
+                match self.run_compile_step(
+                    step,
+                    infile.clone(),
+                    sandbox_outfile.clone(),
+                    dir.path(),
+                )
+                .await
+                {
+                    CompileResult::Success(out) => output.append(out),
+                    err => return err,
+                }
+            }
+            if let Err(err) = std::fs::copy(dir.path().join(out_name), outfile) {
+                return CompileResult::Aborted(format!("Failed to install binary: {}", err));
+            }
+            return CompileResult::Success(output);
+        }
+        // No compile step: the source itself is installed as `outfile`
+        // and `exec_cmd` runs it through the interpreter.
+        let res = if let Some(check_exec) = &self.check_exec {
+            let check = CompileStep {
+                exec: check_exec.clone(),
+                args: self.check_args.clone().unwrap_or_default(),
+            };
+            self.run_compile_step(&check, infile, sandbox_outfile, dir.path())
+                .await
         } else {
-            CompileResult::Error(String::from_utf8(cmd.stderr).unwrap())
+            CompileResult::Success(CompileOutput::default())
+        };
+        if let CompileResult::Success(_) = res {
+            use std::os::unix::fs::PermissionsExt;
+            if let Err(err) = std::fs::copy(path, outfile.clone()) {
+                return CompileResult::Aborted(format!("Failed to install source: {}", err));
+            }
+            std::fs::set_permissions(outfile, std::fs::Permissions::from_mode(0o755)).ok();
         }
+        res
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Languages {
     langs: HashMap<Uuid, Language>,
+    disabled: Vec<DisabledLanguage>,
 }
 
 impl Languages {
@@ -193,22 +714,65 @@ impl Languages {
         let dir = Path::new(&binding);
         assert_eq!(dir.is_dir(), true);
         let mut map = HashMap::new();
+        let mut disabled = vec![];
         for entry in read_dir(dir)? {
             let entry = entry?;
             if let Ok(file_t) = entry.file_type() {
                 if file_t.is_file() {
                     let path = entry.path();
                     let s = read_to_string(path).expect("Some error occured");
-                    if let Ok(lang) = toml::from_str::<Language>(&s) {
-                        map.insert(lang.uuid.clone(), lang.clone());
+                    if let Ok(mut lang) = toml::from_str::<Language>(&s) {
+                        match lang.validate().and_then(|_| lang.probe_toolchain()) {
+                            Ok(toolchain) => {
+                                if let Some(toolchain) = &toolchain {
+                                    info!("Language {} ({}): {}", lang.name, lang.uuid, toolchain);
+                                }
+                                lang.toolchain = toolchain;
+                                lang.fingerprint = lang.toolchain_fingerprint();
+                                map.insert(lang.uuid.clone(), lang.clone());
+                            }
+                            Err(reason) => {
+                                warn!(
+                                    "Language {} ({}) was disabled: {}",
+                                    lang.name, lang.uuid, reason
+                                );
+                                disabled.push(DisabledLanguage {
+                                    uuid: lang.uuid,
+                                    name: lang.name.clone(),
+                                    reason,
+                                });
+                            }
+                        }
                     }
                 }
             }
         }
-        Ok(Self { langs: map })
+        Ok(Self {
+            langs: map,
+            disabled,
+        })
+    }
+
+    /// Takes `langs` as they are, without validating or probing them.
+    #[cfg(test)]
+    pub fn with(langs: Vec<Language>) -> Self {
+        Self {
+            langs: langs.into_iter().map(|lang| (lang.uuid, lang)).collect(),
+            disabled: vec![],
+        }
     }
 
     pub fn get(&self, id: Uuid) -> Option<&Language> {
         self.langs.get(&id)
     }
+
+    pub fn describe(&self) -> Vec<LanguageInfo> {
+        let mut infos: Vec<LanguageInfo> = self.langs.values().map(|lang| lang.info()).collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    pub fn disabled(&self) -> Vec<DisabledLanguage> {
+        self.disabled.clone()
+    }
 }
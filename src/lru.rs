@@ -0,0 +1,67 @@
+//! Directories of plain files on local disk, written atomically and evicted
+//! by least recent use. Shared by the disk cache and the stores.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use tempfile::NamedTempFile;
+
+lazy_static! {
+    static ref EVICTION: Mutex<()> = Mutex::new(());
+}
+
+/// A temporary file in `temp_dir`, to be moved into place by `persist`.
+pub fn temp_file(temp_dir: &Path) -> io::Result<NamedTempFile> {
+    fs::create_dir_all(temp_dir)?;
+    NamedTempFile::new_in(temp_dir)
+}
+
+/// Syncs `temp` and moves it to `path`, so readers never see a partial file.
+pub fn persist(temp: NamedTempFile, path: &Path) -> io::Result<()> {
+    temp.as_file().sync_all()?;
+    temp.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Marks `path` as recently used.
+pub fn touch(path: &Path) -> io::Result<()> {
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Removes the least recently used files of `dir` until they fit `max_size`.
+pub fn evict(dir: &Path, max_size: u64) {
+    let _guard = EVICTION.lock().unwrap();
+    let mut entries: Vec<(SystemTime, u64, _)> = match fs::read_dir(dir) {
+        Ok(dir) => dir
+            .flatten()
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok()?;
+                if !meta.is_file() {
+                    return None;
+                }
+                Some((meta.modified().ok()?, meta.len(), entry.path()))
+            })
+            .collect(),
+        Err(_) => return,
+    };
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    if total <= max_size {
+        return;
+    }
+    entries.sort_by_key(|(modified, _, _)| *modified);
+    for (_, len, path) in entries {
+        if total <= max_size {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            debug!("Evicted {}", path.display());
+            total -= len;
+        }
+    }
+}
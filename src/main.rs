@@ -4,12 +4,20 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+mod archive;
+mod cache;
+mod capability;
 mod config;
 mod constants;
 mod container;
+mod diagnostics;
+mod graders;
 mod judge;
 mod language;
+mod lru;
 mod protocol;
+mod session;
+mod store;
 mod timer;
 
 #[cfg(test)]
@@ -17,8 +25,6 @@ mod tests;
 
 pub const CONFIG_FILE: &'static str = "config.toml";
 
-use std::fs::read_to_string;
-
 use log::*;
 
 use config::Config;
@@ -27,19 +33,46 @@ use language::Languages;
 use protocol::open_protocol;
 
 lazy_static! {
-    static ref CONFIG: Config = {
-        let s = read_to_string(CONFIG_FILE).expect("Some error occured");
-        println!("loaded pms-slave config file");
-        toml::from_str(&s).expect("Some error occured")
-    };
-    static ref LANGUAGES: Languages = Languages::load().expect("Some error occured");
+    static ref CONFIG: Config = load_config();
+    static ref LANGUAGES: Languages = load_languages();
     static ref MASTER_PASS: Vec<u8> = blake3::hash(CONFIG.host.master_pass.as_bytes()).as_bytes().to_vec();
 }
 
+#[cfg(not(test))]
+fn load_config() -> Config {
+    let s = std::fs::read_to_string(CONFIG_FILE).expect("Some error occured");
+    println!("loaded pms-slave config file");
+    toml::from_str(&s).expect("Some error occured")
+}
+
+#[cfg(not(test))]
+fn load_languages() -> Languages {
+    Languages::load().expect("Some error occured")
+}
+
+#[cfg(test)]
+use tests::{config as load_config, languages as load_languages};
+
 #[async_std::main]
 async fn main() {
     log4rs::init_file(LOG_CONFIG_FILE, Default::default()).unwrap();
     info!("pms-slave {}", env!("CARGO_PKG_VERSION"));
     CONFIG.validate();
-    open_protocol().await
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        None => {
+            lazy_static::initialize(&LANGUAGES);
+            open_protocol().await
+        }
+        Some("purge-cache") => {
+            let removed = cache::purge(args.get(2).map(String::as_str)).await;
+            info!("Removed {} cache entries", removed);
+        }
+        Some(cmd) => {
+            error!(
+                "Unknown command `{}`. Usage: pms-slave [purge-cache [NAMESPACE]]",
+                cmd
+            );
+        }
+    }
 }
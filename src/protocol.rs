@@ -1,12 +1,10 @@
-use async_compression::futures::bufread::BrotliDecoder;
 use async_std::fs::DirBuilder;
 use async_std::io::BufReader;
 use async_std::net::TcpStream;
 use async_std::prelude::*;
-use async_std::task::{spawn, sleep};
+use async_std::task::{spawn, spawn_blocking, sleep};
 use async_std::channel::{unbounded, Receiver, Sender};
 use async_std::sync::*;
-use async_tar::Archive;
 
 use bincode::Options;
 
@@ -20,39 +18,44 @@ use k256::ecdh::EphemeralSecret;
 use k256::ecdh::SharedSecret;
 use k256::PublicKey;
 use rand::thread_rng;
+use serde::Serialize;
 use tempfile::NamedTempFile;
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::Path;
 use std::pin::Pin;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use redis::Client;
 
 use uuid::Uuid;
 
+use crate::archive;
+use crate::capability::Capabilities;
 use crate::constants::*;
-use crate::container::*;
+use crate::graders::Graders;
 use crate::judge::*;
-use crate::language::{compile_with_graders, CompileResult};
+use crate::language::{compile_with_graders, CompileResult, Language};
+use crate::session::JudgeSession;
+use crate::store;
 use crate::timer::*;
 use crate::{CONFIG, LANGUAGES, MASTER_PASS};
 
 #[derive(Clone, Copy, Debug)]
 pub enum Actions {
-    Reconnect(u64),
+    Reconnect,
     Shutdown,
     Unknown,
 }
 
 struct State {
     key: Arc<EphemeralSecret>,
-    locked: RwLock<bool>,
     node_id: RwLock<u32>,
     shared: Arc<RwLock<Option<SharedSecret>>>,
     signal: Sender<Actions>,
-    judge: Arc<RwLock<Option<OnJudge>>>,
+    session: Arc<Mutex<JudgeSession>>,
+    heartbeat: Mutex<Heartbeat>,
+    backoff: Arc<Mutex<Backoff>>,
 }
 
 impl State {
@@ -66,22 +69,21 @@ impl State {
         packet.send(Arc::clone(&stream)).await
     }
 
-    async fn update_judge(
+    /// Sends `req` as the body of `command`, along with this connection's
+    /// node id and public key.
+    async fn send_after_handshake<T: Serialize>(
         &self,
         stream: Arc<TcpStream>,
-        uuid: Uuid,
-        state: JudgeState,
+        command: Command,
+        req: T,
     ) -> async_std::io::Result<()> {
         let body = BodyAfterHandshake {
             node_id: *self.node_id.read().await,
             client_pubkey: self.key.public_key(),
-            req: JudgeResponseBody {
-                uuid,
-                result: state,
-            },
+            req,
         };
         let packet = Packet::make_packet(
-            Command::GetJudgeStateUpdate,
+            command,
             bincode::DefaultOptions::new()
                 .with_big_endian()
                 .with_fixint_encoding()
@@ -91,6 +93,99 @@ impl State {
         packet.send(Arc::clone(&stream)).await
     }
 
+    async fn send_capabilities(&self, stream: Arc<TcpStream>) -> async_std::io::Result<()> {
+        self.send_after_handshake(stream, Command::UpdateCapabilities, Capabilities::current())
+            .await
+    }
+
+    async fn update_judge(
+        &self,
+        stream: Arc<TcpStream>,
+        uuid: Uuid,
+        state: JudgeState,
+    ) -> async_std::io::Result<()> {
+        let req = JudgeResponseBody {
+            uuid,
+            result: state,
+        };
+        self.send_after_handshake(stream, Command::GetJudgeStateUpdate, req)
+            .await
+    }
+
+    /// Sends a `Command::Ping` or answers one with a `Command::Pong`.
+    async fn send_heartbeat(
+        &self,
+        stream: Arc<TcpStream>,
+        command: Command,
+        seq: u64,
+    ) -> async_std::io::Result<()> {
+        self.send_after_handshake(stream, command, seq).await
+    }
+
+    /// Pings the master every `heartbeat.interval` once the handshake is done,
+    /// and asks to reconnect once the handshake or a ping is unanswered for
+    /// `heartbeat.timeout`. Stops with the connection.
+    async fn keep_alive(&self, stream: Arc<TcpStream>) {
+        let timeout = Duration::from_secs(CONFIG.heartbeat.timeout);
+        let started = Instant::now();
+        loop {
+            sleep(Duration::from_secs(CONFIG.heartbeat.interval)).await;
+            if self.signal.is_closed() {
+                return;
+            }
+            if self.shared.read().await.is_none() {
+                if started.elapsed() > timeout {
+                    warn!(
+                        "Master did not answer the handshake for {} secs. Trying to reconnect ...",
+                        CONFIG.heartbeat.timeout
+                    );
+                    self.signal.send(Actions::Reconnect).await.ok();
+                    return;
+                }
+                continue;
+            }
+            if self.heartbeat.lock().await.is_timed_out(timeout) {
+                warn!(
+                    "Master did not answer a ping for {} secs. Trying to reconnect ...",
+                    CONFIG.heartbeat.timeout
+                );
+                self.signal.send(Actions::Reconnect).await.ok();
+                return;
+            }
+            let seq = self.heartbeat.lock().await.ping();
+            if let Err(err) = self
+                .send_heartbeat(Arc::clone(&stream), Command::Ping, seq)
+                .await
+            {
+                error!("Unable to ping master: {:?}. Trying to reconnect ...", err);
+                self.signal.send(Actions::Reconnect).await.ok();
+                return;
+            }
+        }
+    }
+
+    /// Sends the diagnostics found in compiler `output` when `lang` declares a
+    /// format, and returns the output to report as text.
+    async fn report_diagnostics(
+        &self,
+        stream: Arc<TcpStream>,
+        uuid: Uuid,
+        lang: &Language,
+        output: String,
+    ) -> String {
+        let format = match &lang.diagnostics {
+            Some(format) => format,
+            None => return output,
+        };
+        let (output, diagnostics) = format.parse(&output);
+        if !diagnostics.is_empty() {
+            self.update_judge(stream, uuid, JudgeState::CompileDiagnostics(diagnostics))
+                .await
+                .ok();
+        }
+        output
+    }
+
     async fn handle_command(&self, stream: Arc<TcpStream>, packet: Packet) {
         match packet.heady.header.command {
             Command::Handshake => {
@@ -109,6 +204,13 @@ impl State {
                                 "Handshake was established from remote {}",
                                 stream.peer_addr().unwrap()
                             );
+                            self.backoff.lock().await.reset();
+                            if let Err(err) = self.send_capabilities(Arc::clone(&stream)).await {
+                                error!("Unable to send capabilities to master: {:?}", err);
+                            }
+                            if let Err(err) = self.resume_judge(Arc::clone(&stream)).await {
+                                error!("Unable to resume judge: {:?}", err);
+                            }
                         }
                         HandshakeResult::PasswordNotMatched => {
                             error!("Master password is not matched. Trying to shutdown ...");
@@ -119,11 +221,8 @@ impl State {
                         }
                     }
                 } else {
-                    error!("An error occurred on processing Command::Handshake. Trying to reconnect in {} secs ...", SLEEP_TIME);
-                    self.signal
-                        .send(Actions::Reconnect(SLEEP_TIME))
-                        .await
-                        .ok();
+                    error!("An error occurred on processing Command::Handshake. Trying to reconnect ...");
+                    self.signal.send(Actions::Reconnect).await.ok();
                 }
             }
             Command::ReqVerifyToken => {
@@ -134,22 +233,43 @@ impl State {
                 {
                     if !state {
                         info!("Session was expired. Trying to reconnect ...");
-                        self.signal.send(Actions::Reconnect(0)).await;
+                        self.signal.send(Actions::Reconnect).await;
                     } else {
                         info!("Command::VerifyToken was succeed");
                     }
                 } else {
-                    error!("An error occurred on processing Command::ReqVerifyToken. Trying to reconnect in {} secs ...", SLEEP_TIME);
-                    self.signal
-                        .send(Actions::Reconnect(SLEEP_TIME))
-                        .await
-                        .ok();
+                    error!("An error occurred on processing Command::ReqVerifyToken. Trying to reconnect ...");
+                    self.signal.send(Actions::Reconnect).await.ok();
+                }
+            }
+            Command::Ping => {
+                if let Ok(seq) = bincode::DefaultOptions::new()
+                    .with_big_endian()
+                    .with_fixint_encoding()
+                    .deserialize::<u64>(&packet.heady.body)
+                {
+                    self.send_heartbeat(stream, Command::Pong, seq).await.ok();
+                }
+            }
+            Command::Pong => {
+                if let Ok(seq) = bincode::DefaultOptions::new()
+                    .with_big_endian()
+                    .with_fixint_encoding()
+                    .deserialize::<u64>(&packet.heady.body)
+                {
+                    if let Some(rtt) = self.heartbeat.lock().await.pong(seq) {
+                        let mut latency = LATENCY.lock().await;
+                        latency.record(rtt);
+                        debug!("Master latency: {:?}", rtt);
+                        if latency.samples % LATENCY_REPORT_INTERVAL == 0 {
+                            info!("Master latency: {}", latency);
+                        }
+                    }
                 }
             }
             Command::TestCaseEnd => {
                 trace!("end judge");
-                *self.judge.write().await = None;
-                *self.locked.write().await = false;
+                self.session.lock().await.finish();
             }
             Command::TestCaseUpdate => {
                 if let Ok(test) = bincode::DefaultOptions::new()
@@ -157,311 +277,7 @@ impl State {
                     .with_fixint_encoding()
                     .deserialize::<TestCaseUpdateBody>(&packet.heady.body)
                 {
-                    if let Some(onjudge) = self.judge.read().await.as_ref() {
-                        if onjudge.uuid == test.uuid {
-                            if *self.locked.read().await {
-                                if let Some(shared_key) = self.shared.read().await.as_ref() {
-                                    let key = expand_key(shared_key);
-                                    let (stdin, stdout_origin) =
-                                        (test.stdin.decrypt(&key), test.stdout.decrypt(&key));
-                                    let run_tempdir = tempfile::tempdir().unwrap();
-                                    let (stdin_p, stdout_origin_p, stdout_p) = (
-                                        onjudge.tempdir.path().join(STDIN_FILE_NAME),
-                                        onjudge.tempdir.path().join(STDOUT_ORIGIN_FILE_NAME),
-                                        onjudge.tempdir.path().join(STDOUT_FILE_NAME),
-                                    );
-                                    let (mut stdin_f, mut stdout_origin_f, mut stdout_f) = (
-                                        std::fs::File::create(stdin_p.clone()).unwrap(),
-                                        std::fs::File::create(stdout_origin_p.clone()).unwrap(),
-                                        std::fs::File::create(stdout_p.clone()).unwrap(),
-                                    );
-                                    use std::os::unix::fs::PermissionsExt;
-                                    stdout_f.flush().ok();
-                                    std::fs::set_permissions(
-                                        onjudge.tempdir.path().to_path_buf(),
-                                        std::fs::Permissions::from_mode(0o777),
-                                    )
-                                    .ok();
-                                    std::fs::set_permissions(
-                                        stdout_p.clone(),
-                                        std::fs::Permissions::from_mode(0o777),
-                                    )
-                                    .ok();
-                                    stdin_f.write_all(&stdin).ok();
-                                    stdin_f.flush().ok();
-                                    stdout_origin_f.write_all(&stdout_origin).ok();
-                                    stdout_origin_f.flush().ok();
-                                    if let (Some(manager_lang), Some(object_path)) = (
-                                        onjudge.manager_lang.clone(),
-                                        onjudge.object_binary.clone(),
-                                    ) {
-                                        // 'Novel' mode
-                                        let run = Runv2 {
-                                            temp_path: onjudge.tempdir.path().to_path_buf(),
-                                            object_path: object_path,
-                                            box_dir: run_tempdir,
-                                            main_lang: onjudge.main_lang.clone(),
-                                            manager_lang: manager_lang,
-                                            time_limit: (onjudge.time_limit as f64)
-                                                * CONVERT_TO_SECONDS,
-                                            mem_limit: onjudge.mem_limit,
-                                            procs: onjudge.procs,
-                                        };
-                                        let res = run.run();
-                                        debug!(
-                                            "(Judge: {}) (Test: {}) {:?}",
-                                            onjudge.uuid,
-                                            test.uuid,
-                                            res.meta.clone()
-                                        );
-                                        if let Some(status) = res.meta.status {
-                                            // Failed?
-                                            match status {
-                                                RunStatus::TimedOut => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::TimeLimitExceed(test.test_uuid),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                RunStatus::DiedOnSignal => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::DiedOnSignal(
-                                                            test.test_uuid,
-                                                            res.meta.exitsig.unwrap(),
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                RunStatus::InternalErr => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::InternalError(test.test_uuid),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                RunStatus::RuntimeErr => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::RuntimeError(
-                                                            test.test_uuid,
-                                                            res.meta.exitcode.unwrap(),
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                _ => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::UnknownError,
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                            }
-                                        } else {
-                                            // Success
-                                            // Let's check stdout by checker
-                                            let dir_checker = tempfile::tempdir().unwrap();
-                                            let checker = CheckerRun {
-                                                checker_lang: onjudge.checker_lang.clone(),
-                                                temp_path: onjudge.tempdir.path().to_path_buf(),
-                                                box_dir: dir_checker,
-                                            };
-                                            let res_checker = checker.run();
-                                            debug!(
-                                                "(Checker) (Judge: {}) (Test: {}) {:?}",
-                                                onjudge.uuid,
-                                                test.uuid,
-                                                res_checker.meta.clone()
-                                            );
-                                            if let Some(status_checker) = res_checker.meta.status {
-                                                self.update_judge(
-                                                    stream,
-                                                    test.uuid,
-                                                    JudgeState::WrongAnswer(
-                                                        test.test_uuid,
-                                                        (res_checker.meta.time.unwrap()
-                                                            * CONVERT_TO_MILLISECS)
-                                                            as u64,
-                                                        res_checker.meta.cg_mem.unwrap(),
-                                                    ),
-                                                )
-                                                .await
-                                                .ok();
-                                            } else {
-                                                self.update_judge(
-                                                    stream,
-                                                    test.uuid,
-                                                    JudgeState::Accepted(
-                                                        test.test_uuid,
-                                                        (res_checker.meta.time.unwrap()
-                                                            * CONVERT_TO_MILLISECS)
-                                                            as u64,
-                                                        res_checker.meta.cg_mem.unwrap(),
-                                                    ),
-                                                )
-                                                .await
-                                                .ok();
-                                            }
-                                        }
-                                    } else {
-                                        // 'Simple' mode
-                                        let run = Run {
-                                            temp_path: onjudge.tempdir.path().to_path_buf(),
-                                            box_dir: run_tempdir,
-                                            language: onjudge.main_lang.clone(),
-                                            time_limit: (onjudge.time_limit as f64)
-                                                * CONVERT_TO_SECONDS,
-                                            mem_limit: onjudge.mem_limit,
-                                        };
-                                        let res = run.run();
-                                        trace!("{:?}", res.meta.clone());
-                                        if let Some(status) = res.meta.status {
-                                            // Failed?
-                                            match status {
-                                                RunStatus::TimedOut => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::TimeLimitExceed(test.test_uuid),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                RunStatus::DiedOnSignal => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::DiedOnSignal(
-                                                            test.test_uuid,
-                                                            res.meta.exitsig.unwrap(),
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                RunStatus::InternalErr => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::InternalError(test.test_uuid),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                RunStatus::RuntimeErr => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::RuntimeError(
-                                                            test.test_uuid,
-                                                            res.meta.exitcode.unwrap(),
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                                _ => {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::UnknownError,
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                            }
-                                        } else {
-                                            // Success
-                                            // Let's check stdout by checker
-                                            let dir_checker = tempfile::tempdir().unwrap();
-                                            let checker = CheckerRun {
-                                                checker_lang: onjudge.checker_lang.clone(),
-                                                temp_path: onjudge.tempdir.path().to_path_buf(),
-                                                box_dir: dir_checker,
-                                            };
-                                            let res_checker = checker.run();
-                                            trace!("{:?}", res_checker.meta);
-                                            if let Some(status_checker) = res_checker.meta.status {
-                                                self.update_judge(
-                                                    stream,
-                                                    test.uuid,
-                                                    JudgeState::WrongAnswer(
-                                                        test.test_uuid,
-                                                        (res_checker.meta.time.unwrap()
-                                                            * CONVERT_TO_MILLISECS)
-                                                            as u64,
-                                                        res_checker.meta.cg_mem.unwrap(),
-                                                    ),
-                                                )
-                                                .await
-                                                .ok();
-                                            } else {
-                                                if let Some(score) = res_checker.score {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::Complete(
-                                                            test.test_uuid,
-                                                            score,
-                                                            (res_checker.meta.time.unwrap()
-                                                                * CONVERT_TO_MILLISECS)
-                                                                as u64,
-                                                            res_checker.meta.cg_mem.unwrap(),
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                } else {
-                                                    self.update_judge(
-                                                        stream,
-                                                        test.uuid,
-                                                        JudgeState::Accepted(
-                                                            test.test_uuid,
-                                                            (res_checker.meta.time.unwrap()
-                                                                * CONVERT_TO_MILLISECS)
-                                                                as u64,
-                                                            res_checker.meta.cg_mem.unwrap(),
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .ok();
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            } else {
-                                error!("Unable to handle Command::TestCaseUpdate (JudgeState::UnlockedSlave)");
-                                self.update_judge(stream, test.uuid, JudgeState::UnlockedSlave)
-                                    .await
-                                    .ok();
-                            }
-                        } else {
-                            error!("Unable to handle Command::TestCaseUpdate (JudgeState::JudgeNotFound)");
-                            self.update_judge(stream, test.uuid, JudgeState::JudgeNotFound)
-                                .await
-                                .ok();
-                        }
-                    } else {
-                        error!(
-                            "Unable to handle Command::TestCaseUpdate (JudgeState::JudgeNotFound)"
-                        );
-                        self.update_judge(stream, test.uuid, JudgeState::JudgeNotFound)
-                            .await
-                            .ok();
-                    }
+                    self.handle_test_case(stream, test).await;
                 }
             }
             Command::GetJudgev2 => {
@@ -471,143 +287,9 @@ impl State {
                     .deserialize::<JudgeRequestBodyv2>(&packet.heady.body)
                 {
                     info!("Got a new judgement (v2) request: {}", judge_req.uuid);
-                    if !(*self.locked.read().await) {
-                        if let (Some(checker_lang), Some(main_lang), Some(manager_lang)) = (
-                            LANGUAGES.get(judge_req.checker_lang),
-                            LANGUAGES.get(judge_req.main_lang),
-                            LANGUAGES.get(judge_req.manager_lang),
-                        ) {
-                            if let Some(shared_key) = self.shared.read().await.as_ref() {
-                                *self.locked.write().await = true;
-                                let key = expand_key(shared_key);
-                                let checker_code = judge_req.checker_code.decrypt(&key);
-                                let main_code = judge_req.main_code.decrypt(&key);
-                                let manager_code = judge_req.manager_code.decrypt(&key);
-                                let graders_buf = judge_req.graders.decrypt(&key);
-                                let graders_hash = *blake3::hash(&graders_buf).as_bytes();
-                                let graders_decoder = BrotliDecoder::new(graders_buf.as_slice());
-                                let decoded = graders_decoder.into_inner();
-                                let graders_ar = Archive::new(decoded);
-                                let dir = tempfile::tempdir().unwrap();
-                                //dbg!(std::process::Command::new("ls -la").current_dir(dir.path()).output().unwrap().stdout);
-                                graders_ar.unpack(dir.path()).await.ok();
-                                self.update_judge(
-                                    Arc::clone(&stream),
-                                    judge_req.uuid,
-                                    JudgeState::DoCompile,
-                                )
-                                .await
-                                .ok();
-                                let c_path = dir.path().join(CHECKER_NAME);
-                                let m_path = dir.path().join(MANAGER_NAME);
-                                let o_path = dir
-                                    .path()
-                                    .join(GRADERS_PATH)
-                                    .join(judge_req.object_path.clone());
-                                let graders_path = dir.path().join(GRADERS_PATH);
-                                let b_compile = compile_with_graders(
-                                    &graders_hash,
-                                    graders_path,
-                                    main_code,
-                                    judge_req.object_path.clone(),
-                                    judge_req.main_path,
-                                );
-                                let c_compile = checker_lang.compile(checker_code, c_path.clone());
-                                let m_compile = manager_lang.compile(manager_code, m_path.clone());
-                                let (b_res, c_res, m_res) = join!(b_compile, c_compile, m_compile);
-                                match b_res {
-                                    CompileResult::Error(stderr) => {
-                                        trace!("Unable to compile main code: {}", stderr);
-                                        self.update_judge(
-                                            Arc::clone(&stream),
-                                            judge_req.uuid,
-                                            JudgeState::CompileError(stderr),
-                                        )
-                                        .await
-                                        .ok();
-                                        *self.locked.write().await = false;
-                                    }
-                                    CompileResult::Success(stdout) => {
-                                        if let (
-                                            CompileResult::Success(_),
-                                            CompileResult::Success(_),
-                                        ) = (c_res.clone(), m_res.clone())
-                                        {
-                                            if !o_path.exists() {
-                                                trace!("o_path is not exists");
-                                                self.update_judge(
-                                                    Arc::clone(&stream),
-                                                    judge_req.uuid,
-                                                    JudgeState::GeneralError(String::from(
-                                                        "o_path is not exists",
-                                                    )),
-                                                )
-                                                .await
-                                                .ok();
-                                                *self.locked.write().await = false;
-                                            } else {
-                                                *self.judge.write().await = Some(OnJudge {
-                                                    uuid: judge_req.uuid,
-                                                    main_lang: main_lang.clone(),
-                                                    checker_lang: checker_lang.clone(),
-                                                    manager_lang: Some(manager_lang.clone()),
-                                                    main_binary: o_path,
-                                                    checker_binary: c_path,
-                                                    object_binary: Some(judge_req.object_path),
-                                                    time_limit: judge_req.time_limit,
-                                                    mem_limit: judge_req.mem_limit,
-                                                    tempdir: dir,
-                                                    procs: judge_req.procs,
-                                                });
-                                                self.update_judge(
-                                                    Arc::clone(&stream),
-                                                    judge_req.uuid,
-                                                    JudgeState::CompleteCompile(stdout),
-                                                )
-                                                .await
-                                                .ok();
-                                            }
-                                        } else {
-                                            error!(
-                                                "Checker or manager compile failed: {:?}, {:?}",
-                                                c_res, m_res
-                                            );
-                                            self.update_judge(
-                                                Arc::clone(&stream),
-                                                judge_req.uuid,
-                                                JudgeState::GeneralError(String::from(
-                                                    "Checker or manager compile failed",
-                                                )),
-                                            )
-                                            .await
-                                            .ok();
-                                            *self.locked.write().await = false;
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            error!("Unable to get judgement languages");
-                            self.update_judge(
-                                Arc::clone(&stream),
-                                judge_req.uuid,
-                                JudgeState::LanguageNotFound,
-                            )
-                            .await
-                            .ok();
-                            *self.locked.write().await = false;
-                        }
-                    } else {
-                        error!("Unable to handle Command::GetJudgev2 (JudgeState::LockedSlave)");
-                        self.update_judge(
-                            Arc::clone(&stream),
-                            judge_req.uuid,
-                            JudgeState::LockedSlave,
-                        )
-                        .await
-                        .ok();
-                        *self.locked.write().await = false;
-                    }
+                    let uuid = judge_req.uuid;
+                    let prepare = self.prepare_judge_v2(Arc::clone(&stream), judge_req);
+                    self.start_judge(stream, uuid, prepare).await;
                 }
             }
             Command::GetJudge => {
@@ -617,126 +299,9 @@ impl State {
                     .deserialize::<JudgeRequestBody>(&packet.heady.body)
                 {
                     info!("Got a new judgement request: {}", judge_req.uuid);
-                    if !(*self.locked.read().await) {
-                        if let Some(checker_lang) = LANGUAGES.get(judge_req.checker_lang.clone()) {
-                            if let Some(main_lang) = LANGUAGES.get(judge_req.main_lang.clone()) {
-                                if let Some(shared_key) = self.shared.read().await.as_ref() {
-                                    let key = expand_key(shared_key);
-                                    let checker_code = judge_req.checker_code.decrypt(&key);
-                                    let main_code = judge_req.main_code.decrypt(&key);
-                                    *self.locked.write().await = true;
-                                    self.update_judge(
-                                        Arc::clone(&stream),
-                                        judge_req.uuid,
-                                        JudgeState::DoCompile,
-                                    )
-                                    .await
-                                    .ok();
-                                    let dir = tempfile::tempdir().unwrap();
-                                    let c_path = dir.path().join(CHECKER_NAME);
-                                    let m_path = dir.path().join(BINARY_NAME);
-                                    let c_res = checker_lang.compile(checker_code, c_path.clone());
-                                    let m_res = main_lang.compile(main_code, m_path.clone());
-                                    if let CompileResult::Error(stderr) = c_res.await {
-                                        trace!("Unable to compile checker code: {}", stderr);
-                                        self.update_judge(
-                                            Arc::clone(&stream),
-                                            judge_req.uuid,
-                                            JudgeState::GeneralError(stderr),
-                                        )
-                                        .await
-                                        .ok();
-                                        *self.locked.write().await = false;
-                                    } else {
-                                        match m_res.await {
-                                            CompileResult::Error(stderr) => {
-                                                trace!("Unable to compile main code: {}", stderr);
-                                                self.update_judge(
-                                                    Arc::clone(&stream),
-                                                    judge_req.uuid,
-                                                    JudgeState::CompileError(stderr),
-                                                )
-                                                .await
-                                                .ok();
-                                                *self.locked.write().await = false;
-                                            }
-                                            CompileResult::Success(stdout) => {
-                                                use std::os::unix::fs::PermissionsExt;
-                                                std::fs::set_permissions(
-                                                    dir.path().to_path_buf(),
-                                                    std::fs::Permissions::from_mode(0o777),
-                                                )
-                                                .ok();
-                                                *self.judge.write().await = Some(OnJudge {
-                                                    uuid: judge_req.uuid,
-                                                    main_lang: main_lang.clone(),
-                                                    checker_lang: checker_lang.clone(),
-                                                    manager_lang: None,
-                                                    main_binary: m_path,
-                                                    checker_binary: c_path,
-                                                    object_binary: None,
-                                                    time_limit: judge_req.time_limit,
-                                                    mem_limit: judge_req.mem_limit,
-                                                    tempdir: dir,
-                                                    procs: 1,
-                                                });
-                                                self.update_judge(
-                                                    Arc::clone(&stream),
-                                                    judge_req.uuid,
-                                                    JudgeState::CompleteCompile(stdout),
-                                                )
-                                                .await
-                                                .ok();
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    error!("Command::Handshake must be satisfied first");
-                                    self.update_judge(
-                                        Arc::clone(&stream),
-                                        judge_req.uuid,
-                                        JudgeState::GeneralError(String::new()),
-                                    )
-                                    .await
-                                    .ok();
-                                    *self.locked.write().await = false;
-                                }
-                            } else {
-                                error!(
-                                    "Unable to get main code language {}",
-                                    judge_req.main_lang.clone()
-                                );
-                                self.update_judge(
-                                    Arc::clone(&stream),
-                                    judge_req.uuid,
-                                    JudgeState::LanguageNotFound,
-                                )
-                                .await
-                                .ok();
-                            }
-                        } else {
-                            error!(
-                                "Unable to get checker code language {}",
-                                judge_req.checker_lang.clone()
-                            );
-                            self.update_judge(
-                                Arc::clone(&stream),
-                                judge_req.uuid,
-                                JudgeState::LanguageNotFound,
-                            )
-                            .await
-                            .ok();
-                        }
-                    } else {
-                        error!("Unable to handle Command::GetJudge (JudgeState::LockedSlave)");
-                        self.update_judge(
-                            Arc::clone(&stream),
-                            judge_req.uuid,
-                            JudgeState::LockedSlave,
-                        )
-                        .await
-                        .ok();
-                    }
+                    let uuid = judge_req.uuid;
+                    let prepare = self.prepare_judge(Arc::clone(&stream), judge_req);
+                    self.start_judge(stream, uuid, prepare).await;
                 }
             }
             _ => {
@@ -745,90 +310,507 @@ impl State {
             }
         }
     }
-}
 
-pub async fn open_protocol() {
-    loop {
-        let mut shutdown = false;
-        // do master connection loop
-        if let Ok(stream) = TcpStream::connect(CONFIG.host.master.clone()).await {
-            let stream: Arc<TcpStream> = Arc::new(stream);
-            let key = EphemeralSecret::random(thread_rng());
-            let (send, mut recv): (Sender<Actions>, Receiver<Actions>) = unbounded();
-            let state = Arc::new(State {
-                key: Arc::new(key),
-                locked: RwLock::new(false),
-                node_id: RwLock::new(std::u32::MAX),
-                shared: Arc::new(RwLock::new(None)),
-                signal: send.clone(),
-                judge: Arc::new(RwLock::new(None)),
-            });
-            let handshake_req = HandshakeRequest {
-                client_pubkey: state.key.public_key(),
-                pass: MASTER_PASS.clone(),
-            };
-            // Send Handshake packet
-            let handshake = Packet::make_packet(
-                Command::Handshake,
-                bincode::DefaultOptions::new()
-                    .with_big_endian()
-                    .with_fixint_encoding()
-                    .serialize(&handshake_req)
-                    .unwrap(),
+    /// Locks the session for judge `uuid` while `prepare` builds it, then
+    /// holds the judge for its tests or unlocks the session on failure.
+    async fn start_judge<F>(&self, stream: Arc<TcpStream>, uuid: Uuid, prepare: F)
+    where
+        F: Future<Output = Result<(OnJudge, String), JudgeState>>,
+    {
+        if let Err(state) = self.session.lock().await.begin_compile(uuid) {
+            error!("Unable to start judge {}: the slave is locked", uuid);
+            self.update_judge(stream, uuid, state).await.ok();
+            return;
+        }
+        let state = match prepare.await {
+            Ok((judge, message)) => match self.session.lock().await.compiled(judge) {
+                Ok(()) => JudgeState::CompleteCompile(message),
+                Err(_) => {
+                    warn!("Judge {} was ended while compiling", uuid);
+                    return;
+                }
+            },
+            Err(state) => {
+                self.session.lock().await.abort(uuid);
+                state
+            }
+        };
+        let compiled = matches!(state, JudgeState::CompleteCompile(_));
+        if self.update_judge(stream, uuid, state).await.is_err() && compiled {
+            // The master never learns that the judge is ready, so it can't resume it
+            warn!("Lost the connection while compiling judge {}. Dropping it ...", uuid);
+            self.session.lock().await.finish();
+        }
+    }
+
+    /// Tells the master after a handshake which judge the slave still holds,
+    /// so that it sends the remaining tests.
+    async fn resume_judge(&self, stream: Arc<TcpStream>) -> async_std::io::Result<()> {
+        let uuid = match self.session.lock().await.resume() {
+            Some(uuid) => uuid,
+            None => return Ok(()),
+        };
+        info!("Resuming judge {}", uuid);
+        self.send_after_handshake(stream, Command::ResumeJudge, uuid)
+            .await
+    }
+
+    /// Compiles the checker and main code of a 'simple' judge.
+    async fn prepare_judge(
+        &self,
+        stream: Arc<TcpStream>,
+        judge_req: JudgeRequestBody,
+    ) -> Result<(OnJudge, String), JudgeState> {
+        let checker_lang = LANGUAGES.get(judge_req.checker_lang.clone()).ok_or_else(|| {
+            error!(
+                "Unable to get checker code language {}",
+                judge_req.checker_lang.clone()
             );
-            handshake.send(Arc::clone(&stream)).await.ok();
+            JudgeState::LanguageNotFound
+        })?;
+        let main_lang = LANGUAGES.get(judge_req.main_lang.clone()).ok_or_else(|| {
+            error!(
+                "Unable to get main code language {}",
+                judge_req.main_lang.clone()
+            );
+            JudgeState::LanguageNotFound
+        })?;
+        let key = match self.shared.read().await.as_ref() {
+            Some(shared_key) => expand_key(shared_key),
+            None => {
+                error!("Command::Handshake must be satisfied first");
+                return Err(JudgeState::GeneralError(String::new()));
+            }
+        };
+        let checker_code = judge_req.checker_code.decrypt(&key);
+        let main_code = judge_req.main_code.decrypt(&key);
+        let missing = missing_binaries(&[(
+            checker_lang,
+            judge_req.checker_hash.as_ref(),
+            &checker_code,
+            Path::new(CHECKER_NAME),
+        )]);
+        if !missing.is_empty() {
+            debug!("Asking master for sources of {:?}", missing);
+            return Err(JudgeState::ArtifactNotFound(missing));
+        }
+        self.update_judge(Arc::clone(&stream), judge_req.uuid, JudgeState::DoCompile)
+            .await
+            .ok();
+        let dir = tempfile::tempdir().unwrap();
+        let c_path = dir.path().join(CHECKER_NAME);
+        let m_path = dir.path().join(BINARY_NAME);
+        let c_res = checker_lang.compile_stored(
+            judge_req.checker_hash.as_deref(),
+            checker_code,
+            c_path.clone(),
+        );
+        let m_res = main_lang.compile(main_code, m_path.clone());
+        if let CompileResult::Error(stderr)
+        | CompileResult::TimeLimitExceed(stderr)
+        | CompileResult::Aborted(stderr) = c_res.await
+        {
+            trace!("Unable to compile checker code: {}", stderr);
+            return Err(JudgeState::GeneralError(stderr));
+        }
+        let output = match m_res.await {
+            CompileResult::Error(stderr) => {
+                trace!("Unable to compile main code: {}", stderr);
+                let stderr = self
+                    .report_diagnostics(Arc::clone(&stream), judge_req.uuid, main_lang, stderr)
+                    .await;
+                return Err(JudgeState::CompileError(stderr));
+            }
+            CompileResult::TimeLimitExceed(stderr) => {
+                trace!("Compiling main code timed out: {}", stderr);
+                return Err(JudgeState::CompileTimeLimitExceed(stderr));
+            }
+            CompileResult::Aborted(reason) => {
+                error!("Compiling main code was aborted: {}", reason);
+                return Err(JudgeState::GeneralError(reason));
+            }
+            CompileResult::Success(output) => output,
+        };
+        let message = self
+            .report_diagnostics(stream, judge_req.uuid, main_lang, output.message())
+            .await;
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            dir.path().to_path_buf(),
+            std::fs::Permissions::from_mode(0o777),
+        )
+        .ok();
+        let judge = OnJudge {
+            uuid: judge_req.uuid,
+            main_lang: main_lang.clone(),
+            checker_lang: checker_lang.clone(),
+            manager_lang: None,
+            main_binary: m_path,
+            checker_binary: c_path,
+            object_binary: None,
+            grader_files: vec![],
+            time_limit: judge_req.time_limit,
+            mem_limit: judge_req.mem_limit,
+            tempdir: dir,
+            procs: 1,
+        };
+        Ok((judge, message))
+    }
+
+    /// Unpacks the graders and builds them with the main code, alongside the
+    /// checker and manager of a 'novel' judge.
+    async fn prepare_judge_v2(
+        &self,
+        stream: Arc<TcpStream>,
+        judge_req: JudgeRequestBodyv2,
+    ) -> Result<(OnJudge, String), JudgeState> {
+        let (checker_lang, main_lang, manager_lang) = match (
+            LANGUAGES.get(judge_req.checker_lang),
+            LANGUAGES.get(judge_req.main_lang),
+            LANGUAGES.get(judge_req.manager_lang),
+        ) {
+            (Some(checker_lang), Some(main_lang), Some(manager_lang)) => {
+                (checker_lang, main_lang, manager_lang)
+            }
+            _ => {
+                error!("Unable to get judgement languages");
+                return Err(JudgeState::LanguageNotFound);
+            }
+        };
+        let key = match self.shared.read().await.as_ref() {
+            Some(shared_key) => expand_key(shared_key),
+            None => {
+                error!("Command::Handshake must be satisfied first");
+                return Err(JudgeState::GeneralError(String::new()));
+            }
+        };
+        let checker_code = judge_req.checker_code.decrypt(&key);
+        let main_code = judge_req.main_code.decrypt(&key);
+        let manager_code = judge_req.manager_code.decrypt(&key);
+        let missing = missing_binaries(&[
+            (
+                checker_lang,
+                judge_req.checker_hash.as_ref(),
+                &checker_code,
+                Path::new(CHECKER_NAME),
+            ),
+            (
+                manager_lang,
+                judge_req.manager_hash.as_ref(),
+                &manager_code,
+                Path::new(MANAGER_NAME),
+            ),
+        ]);
+        if !missing.is_empty() {
+            debug!("Asking master for sources of {:?}", missing);
+            return Err(JudgeState::ArtifactNotFound(missing));
+        }
+        let graders_buf = judge_req.graders.decrypt(&key);
+        let graders_hash = *blake3::hash(&graders_buf).as_bytes();
+        let dir = tempfile::tempdir().unwrap();
+        if let Err(err) = archive::extract_compressed(&graders_buf, dir.path()).await {
+            error!("Unable to unpack graders: {}", err);
+            return Err(JudgeState::GeneralError(err));
+        }
+        self.update_judge(Arc::clone(&stream), judge_req.uuid, JudgeState::DoCompile)
+            .await
+            .ok();
+        let c_path = dir.path().join(CHECKER_NAME);
+        let m_path = dir.path().join(MANAGER_NAME);
+        let graders_path = dir.path().join(GRADERS_PATH);
+        let graders = Graders::load(
+            dir.path(),
+            main_lang,
+            judge_req.main_path,
+            judge_req.object_path,
+        )
+        .map_err(|err| {
+            error!("Invalid graders archive: {}", err);
+            JudgeState::GeneralError(err)
+        })?;
+        if let Err(err) = graders.install_source(&graders_path, &main_code) {
+            error!("Unable to install the contestant source: {}", err);
+            return Err(JudgeState::GeneralError(err));
+        }
+        let o_path = graders_path.join(&graders.object);
+        let b_compile = compile_with_graders(
+            main_lang,
+            &graders.build,
+            &graders_hash,
+            graders_path,
+            main_code,
+            graders.object.clone(),
+            graders.source.clone(),
+        );
+        let c_compile = checker_lang.compile_stored(
+            judge_req.checker_hash.as_deref(),
+            checker_code,
+            c_path.clone(),
+        );
+        let m_compile = manager_lang.compile_stored(
+            judge_req.manager_hash.as_deref(),
+            manager_code,
+            m_path.clone(),
+        );
+        let (b_res, c_res, m_res) = join!(b_compile, c_compile, m_compile);
+        let output = match b_res {
+            CompileResult::Error(stderr) => {
+                trace!("Unable to compile main code: {}", stderr);
+                let stderr = self
+                    .report_diagnostics(Arc::clone(&stream), judge_req.uuid, main_lang, stderr)
+                    .await;
+                return Err(JudgeState::CompileError(stderr));
+            }
+            CompileResult::TimeLimitExceed(stderr) => {
+                trace!("Compiling main code timed out: {}", stderr);
+                return Err(JudgeState::CompileTimeLimitExceed(stderr));
+            }
+            CompileResult::Aborted(reason) => {
+                error!("Compiling main code was aborted: {}", reason);
+                return Err(JudgeState::GeneralError(reason));
+            }
+            CompileResult::Success(output) => output,
+        };
+        if !matches!(
+            (&c_res, &m_res),
+            (CompileResult::Success(_), CompileResult::Success(_))
+        ) {
+            error!(
+                "Checker or manager compile failed: {:?}, {:?}",
+                c_res, m_res
+            );
+            return Err(JudgeState::GeneralError(String::from(
+                "Checker or manager compile failed",
+            )));
+        }
+        if !o_path.exists() {
+            trace!("o_path is not exists");
+            return Err(JudgeState::GeneralError(String::from(
+                "o_path is not exists",
+            )));
+        }
+        let message = self
+            .report_diagnostics(stream, judge_req.uuid, main_lang, output.message())
+            .await;
+        let judge = OnJudge {
+            uuid: judge_req.uuid,
+            main_lang: main_lang.clone(),
+            checker_lang: checker_lang.clone(),
+            manager_lang: Some(manager_lang.clone()),
+            main_binary: o_path,
+            checker_binary: c_path,
+            object_binary: Some(graders.object),
+            grader_files: graders.files,
+            time_limit: graders.limits.time_limit.unwrap_or(judge_req.time_limit),
+            mem_limit: graders.limits.mem_limit.unwrap_or(judge_req.mem_limit),
+            tempdir: dir,
+            procs: graders.limits.procs.unwrap_or(judge_req.procs),
+        };
+        Ok((judge, message))
+    }
+
+    /// Runs a test of the held judge. The session is only locked to mark
+    /// the judge busy, so tests of a judge never run concurrently.
+    async fn handle_test_case(&self, stream: Arc<TcpStream>, test: TestCaseUpdateBody) {
+        let (uuid, test_uuid) = (test.uuid, test.test_uuid);
+        let begin = self.session.lock().await.begin_test(uuid, test_uuid);
+        let state = match begin {
+            Ok(judge) => {
+                let state = self.run_test_case(judge, test).await;
+                self.session.lock().await.end_test(uuid, test_uuid);
+                state
+            }
+            Err(state) => {
+                error!("Unable to handle Command::TestCaseUpdate for judge {}", uuid);
+                state
+            }
+        };
+        self.update_judge(stream, uuid, state).await.ok();
+    }
+
+    async fn run_test_case(&self, judge: Arc<OnJudge>, test: TestCaseUpdateBody) -> JudgeState {
+        let key = match self.shared.read().await.as_ref() {
+            Some(shared_key) => expand_key(shared_key),
+            None => {
+                error!("Command::Handshake must be satisfied first");
+                return JudgeState::GeneralError(String::new());
+            }
+        };
+        let (stdin, stdout_origin) = (test.stdin.decrypt(&key), test.stdout.decrypt(&key));
+        let missing = missing_test_data(&[
+            (&stdin, test.stdin_hash.as_ref()),
+            (&stdout_origin, test.stdout_hash.as_ref()),
+        ]);
+        if !missing.is_empty() {
+            debug!("Asking master for test data {:?}", missing);
+            return JudgeState::ArtifactNotFound(missing);
+        }
+        let (stdin_p, stdout_origin_p) = (
+            judge.tempdir.path().join(STDIN_FILE_NAME),
+            judge.tempdir.path().join(STDOUT_ORIGIN_FILE_NAME),
+        );
+        if let Err(err) = install_test_data(&stdin_p, stdin, test.stdin_hash.as_ref()).and_then(
+            |_| install_test_data(&stdout_origin_p, stdout_origin, test.stdout_hash.as_ref()),
+        ) {
+            error!("Unable to install test data: {}", err);
+            return JudgeState::GeneralError(err);
+        }
+        // Runs on the blocking pool, so pings are still answered meanwhile
+        let test_uuid = test.test_uuid;
+        spawn_blocking(move || judge.run_test(test_uuid)).await
+    }
+}
+
+/// Hashes the master referred to without sending their source, which the
+/// binary store doesn't hold either.
+fn missing_binaries(refs: &[(&Language, Option<&String>, &[u8], &Path)]) -> Vec<String> {
+    refs.iter()
+        .filter_map(|(lang, hash, code, outfile)| {
+            let hash = (*hash)?;
+            if code.is_empty() && !store::BINARIES.contains(&lang.store_name(hash, outfile)) {
+                Some(hash.clone())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Hashes of test data the master referred to without sending it, which the
+/// test data store doesn't hold either.
+fn missing_test_data(refs: &[(&Vec<u8>, Option<&String>)]) -> Vec<String> {
+    let empty = blake3::hash(&[]).to_hex();
+    refs.iter()
+        .filter_map(|(data, hash)| {
+            let hash = (*hash)?;
+            if data.is_empty() && hash.as_str() != empty.as_str() && !store::TESTDATA.contains(hash)
             {
-                let send_cloned = send.clone();
-                let stream_cloned = Arc::clone(&stream);
-                spawn(async move { check_alive(send_cloned, stream_cloned).await });
-            }
-            //sleep(Duration::from_secs(1)).await;
-            loop {
-                select! {
-                    actions = recv.next().fuse() => match actions {
-                        Some(action) => match action {
-                            Actions::Reconnect(secs) => {
-                                sleep(Duration::from_secs(secs)).await;
-                                break;
-                            }
-                            Actions::Shutdown => {
-                                shutdown = true;
-                                break;
-                            }
-                            _ => {}
-                        },
-                        None => {}
-                    },
-                packet = Packet::from_stream(Arc::clone(&stream)).fuse() => match packet {
-                    Ok(packet) => {
-                        let stream_cloned = Arc::clone(&stream);
-                        let state_arc = Arc::clone(&state);
-                        spawn(async move {
-                            state_arc
-                                .handle_command(Arc::clone(&stream_cloned), packet)
-                                .await
-                        });
-                    },
-                    Err(err) => {
-                        error!("Got a packet error: {:?}", err);
-                        break;
-                    }
+                Some(hash.clone())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Writes test data to `path`, taking it from the store when only `hash` was sent
+/// and keeping it there when both were.
+fn install_test_data(path: &Path, data: Vec<u8>, hash: Option<&String>) -> Result<(), String> {
+    match hash {
+        Some(hash) if data.is_empty() => {
+            if let Some(stored) = store::TESTDATA.get(hash) {
+                return std::fs::copy(stored, path)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string());
+            }
+            if hash.as_str() != blake3::hash(&[]).to_hex().as_str() {
+                return Err(format!("{} is not in the test data store", hash));
+            }
+        }
+        Some(hash) => {
+            if blake3::hash(&data).to_hex().as_str() != hash.as_str() {
+                return Err(format!("Test data does not match its hash {}", hash));
+            }
+            if let Err(err) = store::TESTDATA.insert(hash, &data) {
+                warn!("Unable to store test data {}: {}", hash, err);
+            }
+        }
+        None => {}
+    }
+    std::fs::write(path, &data).map_err(|err| err.to_string())
+}
+
+/// Talks to the master on `stream` until the connection is lost or the
+/// master asks to stop, and returns what to do next.
+pub async fn serve(
+    stream: TcpStream,
+    session: Arc<Mutex<JudgeSession>>,
+    backoff: Arc<Mutex<Backoff>>,
+) -> Actions {
+    let stream: Arc<TcpStream> = Arc::new(stream);
+    let key = EphemeralSecret::random(thread_rng());
+    let (send, mut recv): (Sender<Actions>, Receiver<Actions>) = unbounded();
+    let state = Arc::new(State {
+        key: Arc::new(key),
+        node_id: RwLock::new(std::u32::MAX),
+        shared: Arc::new(RwLock::new(None)),
+        signal: send.clone(),
+        session,
+        heartbeat: Mutex::new(Heartbeat::default()),
+        backoff,
+    });
+    let handshake_req = HandshakeRequest {
+        client_pubkey: state.key.public_key(),
+        pass: MASTER_PASS.clone(),
+    };
+    // Send Handshake packet
+    let handshake = Packet::make_packet(
+        Command::Handshake,
+        bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding()
+            .serialize(&handshake_req)
+            .unwrap(),
+    );
+    handshake.send(Arc::clone(&stream)).await.ok();
+    {
+        let state_cloned = Arc::clone(&state);
+        let stream_cloned = Arc::clone(&stream);
+        spawn(async move { state_cloned.keep_alive(stream_cloned).await });
+    }
+    loop {
+        select! {
+            actions = recv.next().fuse() => match actions {
+                Some(Actions::Unknown) | None => {}
+                Some(action) => return action,
+            },
+            packet = Packet::from_stream(Arc::clone(&stream)).fuse() => match packet {
+                Ok(packet) => {
+                    let stream_cloned = Arc::clone(&stream);
+                    let state_arc = Arc::clone(&state);
+                    spawn(async move {
+                        state_arc
+                            .handle_command(Arc::clone(&stream_cloned), packet)
+                            .await
+                    });
                 }
+                Err(err) => {
+                    error!("Got a packet error: {:?}", err);
+                    return Actions::Reconnect;
                 }
             }
-            drop(state);
-            drop(recv);
-        } else {
-            error!(
-                "Cannot connect to server {:?}. Trying to connect in {} secs ...",
-                CONFIG.host.master.clone(),
-                SLEEP_TIME
-            );
-            sleep(Duration::from_secs(SLEEP_TIME)).await;
         }
-        if shutdown {
-            info!("Actions::Shutdown was triggered");
-            break;
+    }
+}
+
+pub async fn open_protocol() {
+    // Both outlive a connection, so a judge survives reconnects
+    let session = Arc::new(Mutex::new(JudgeSession::default()));
+    let backoff = Arc::new(Mutex::new(Backoff::new(&CONFIG.reconnect)));
+    loop {
+        // do master connection loop
+        match TcpStream::connect(CONFIG.host.master.clone()).await {
+            Ok(stream) => match serve(stream, Arc::clone(&session), Arc::clone(&backoff)).await {
+                Actions::Shutdown => {
+                    info!("Actions::Shutdown was triggered");
+                    break;
+                }
+                Actions::Reconnect | Actions::Unknown => {}
+            },
+            Err(err) => error!(
+                "Cannot connect to server {:?}: {}",
+                CONFIG.host.master.clone(),
+                err
+            ),
         }
+        let mut backoff = backoff.lock().await;
+        let delay = backoff.next_delay();
+        info!(
+            "Trying to reconnect in {:.1} secs (attempt {}) ...",
+            delay.as_secs_f64(),
+            backoff.attempts()
+        );
+        drop(backoff);
+        sleep(delay).await;
     }
 }
@@ -0,0 +1,135 @@
+//! Lifecycle of the judge held by the slave, driven by protocol commands.
+//! Transitions only check the order of commands; compiling and running
+//! happen outside so the session itself never blocks.
+
+use judge_protocol::judge::JudgeState;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::judge::OnJudge;
+
+pub enum SessionState {
+    Idle,
+    Compiling(Uuid),
+    Ready(Arc<OnJudge>),
+    RunningTest(Arc<OnJudge>, Uuid), // The judge and its test
+    Finished(Uuid),
+}
+
+pub struct JudgeSession {
+    state: SessionState,
+    resumed: bool, // Announced after a reconnect, and no test has arrived since
+}
+
+impl Default for JudgeSession {
+    fn default() -> Self {
+        Self {
+            state: SessionState::Idle,
+            resumed: false,
+        }
+    }
+}
+
+impl JudgeSession {
+    /// The judge the session is busy with, if any.
+    pub fn uuid(&self) -> Option<Uuid> {
+        match &self.state {
+            SessionState::Idle | SessionState::Finished(_) => None,
+            SessionState::Compiling(uuid) => Some(*uuid),
+            SessionState::Ready(judge) | SessionState::RunningTest(judge, _) => Some(judge.uuid),
+        }
+    }
+
+    /// The compiled judge to announce to the master after a reconnect, also
+    /// while it runs a test. It is dropped if the master asks for a new judge
+    /// before sending a test for it.
+    pub fn resume(&mut self) -> Option<Uuid> {
+        match &self.state {
+            SessionState::Ready(judge) | SessionState::RunningTest(judge, _) => {
+                self.resumed = true;
+                Some(judge.uuid)
+            }
+            _ => None,
+        }
+    }
+
+    /// `GetJudge` and `GetJudgev2`: locks the slave for judge `uuid`.
+    pub fn begin_compile(&mut self, uuid: Uuid) -> Result<(), JudgeState> {
+        match &self.state {
+            SessionState::Idle | SessionState::Finished(_) => {}
+            SessionState::Ready(judge) if self.resumed => {
+                info!("Dropping judge {} as the master did not resume it", judge.uuid);
+            }
+            _ => return Err(JudgeState::LockedSlave),
+        }
+        self.state = SessionState::Compiling(uuid);
+        self.resumed = false;
+        Ok(())
+    }
+
+    /// Holds `judge` once its binaries are built. Fails if the judge was
+    /// ended while compiling.
+    pub fn compiled(&mut self, judge: OnJudge) -> Result<(), JudgeState> {
+        match self.state {
+            SessionState::Compiling(uuid) if uuid == judge.uuid => {
+                self.state = SessionState::Ready(Arc::new(judge));
+                Ok(())
+            }
+            _ => Err(JudgeState::JudgeNotFound),
+        }
+    }
+
+    /// Unlocks the slave after judge `uuid` failed to compile.
+    pub fn abort(&mut self, uuid: Uuid) {
+        if matches!(self.state, SessionState::Compiling(current) if current == uuid) {
+            self.state = SessionState::Idle;
+        }
+    }
+
+    /// `TestCaseUpdate`: marks test `test_uuid` of judge `uuid` as running.
+    /// The judge is shared, so the test runs without holding the session.
+    pub fn begin_test(&mut self, uuid: Uuid, test_uuid: Uuid) -> Result<Arc<OnJudge>, JudgeState> {
+        self.state = match std::mem::replace(&mut self.state, SessionState::Idle) {
+            SessionState::Ready(judge) if judge.uuid == uuid => {
+                self.resumed = false;
+                SessionState::RunningTest(judge, test_uuid)
+            }
+            state => {
+                let err = match &state {
+                    SessionState::Finished(finished) if *finished == uuid => {
+                        JudgeState::UnlockedSlave
+                    }
+                    SessionState::RunningTest(judge, running) if judge.uuid == uuid => {
+                        debug!("Test {} of judge {} is still running", running, uuid);
+                        JudgeState::LockedSlave
+                    }
+                    _ => JudgeState::JudgeNotFound,
+                };
+                self.state = state;
+                return Err(err);
+            }
+        };
+        match &self.state {
+            SessionState::RunningTest(judge, _) => Ok(Arc::clone(judge)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns to `Ready` after test `test_uuid` of judge `uuid` has run,
+    /// unless the judge was ended meanwhile.
+    pub fn end_test(&mut self, uuid: Uuid, test_uuid: Uuid) {
+        self.state = match std::mem::replace(&mut self.state, SessionState::Idle) {
+            SessionState::RunningTest(judge, running) if judge.uuid == uuid && running == test_uuid => {
+                SessionState::Ready(judge)
+            }
+            state => state,
+        };
+    }
+
+    /// `TestCaseEnd`: drops the judge and unlocks the slave.
+    pub fn finish(&mut self) {
+        if let Some(uuid) = self.uuid() {
+            self.state = SessionState::Finished(uuid);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Persistent content-addressed stores on local disk. Unlike the compile
+//! cache, entries are plain files that can be copied out as they are.
+//! They are written atomically and evicted by least recent use over `max_size`.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::constants::{STORE_BINARIES_PATH, STORE_TEMP_PATH, STORE_TESTDATA_PATH};
+use crate::lru;
+use crate::CONFIG;
+
+lazy_static! {
+    /// Compiled checkers and managers, see `Language::compile_stored`
+    pub static ref BINARIES: Store = Store::new(
+        Path::new(&CONFIG.store.path).join(STORE_BINARIES_PATH),
+        CONFIG.store.binaries_max_size,
+    );
+    /// Decrypted test inputs and expected outputs, named by their blake3 hex hash
+    pub static ref TESTDATA: Store = Store::new(
+        Path::new(&CONFIG.store.path).join(STORE_TESTDATA_PATH),
+        CONFIG.store.testdata_max_size,
+    );
+}
+
+pub struct Store {
+    root: PathBuf,
+    max_size: u64,
+}
+
+impl Store {
+    pub fn new(root: PathBuf, max_size: u64) -> Self {
+        Self { root, max_size }
+    }
+
+    /// Names come from the master, so only blake3 hex hashes are accepted.
+    fn entry_path(&self, name: &str) -> Option<PathBuf> {
+        if name.len() == blake3::OUT_LEN * 2 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Some(self.root.join(name))
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entry_path(name).map_or(false, |path| path.is_file())
+    }
+
+    /// Returns the path of entry `name` and marks it as recently used.
+    pub fn get(&self, name: &str) -> Option<PathBuf> {
+        let path = self.entry_path(name)?;
+        lru::touch(&path).ok()?;
+        Some(path)
+    }
+
+    pub fn insert(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        let mut temp = self.temp_file()?;
+        temp.write_all(data)?;
+        self.persist(name, temp)
+    }
+
+    pub fn insert_file(&self, name: &str, src: &Path) -> std::io::Result<()> {
+        let mut temp = self.temp_file()?;
+        std::io::copy(&mut File::open(src)?, &mut temp)?;
+        self.persist(name, temp)
+    }
+
+    fn temp_file(&self) -> std::io::Result<tempfile::NamedTempFile> {
+        lru::temp_file(&self.root.join(STORE_TEMP_PATH))
+    }
+
+    fn persist(&self, name: &str, temp: tempfile::NamedTempFile) -> std::io::Result<()> {
+        let path = self.entry_path(name).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid store entry name")
+        })?;
+        lru::persist(temp, &path)?;
+        lru::evict(&self.root, self.max_size);
+        Ok(())
+    }
+}
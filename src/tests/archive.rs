@@ -0,0 +1,155 @@
+use async_tar::{EntryType, Header};
+use std::path::Path;
+
+use crate::archive::{extract, extract_bounded};
+use crate::config::Archive;
+
+/// A raw tar entry. Names and link targets are written as they are,
+/// so unlike `async_tar::Builder` it can produce malicious archives.
+fn entry(tar: &mut Vec<u8>, entry_type: EntryType, name: &str, link: &str, data: &[u8]) {
+    let mut header = Header::new_gnu();
+    let old = header.as_old_mut();
+    old.name[..name.len()].copy_from_slice(name.as_bytes());
+    old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+    header.set_entry_type(entry_type);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.extend_from_slice(header.as_bytes());
+    tar.extend_from_slice(data);
+    tar.resize(tar.len() + (512 - data.len() % 512) % 512, 0);
+}
+
+fn file(tar: &mut Vec<u8>, name: &str, data: &str) {
+    entry(tar, EntryType::Regular, name, "", data.as_bytes());
+}
+
+fn link(tar: &mut Vec<u8>, name: &str, target: &str) {
+    entry(tar, EntryType::Symlink, name, target, &[]);
+}
+
+async fn unpack_with(tar: Vec<u8>, limits: &Archive) -> (tempfile::TempDir, Result<(), String>) {
+    let mut tar = tar;
+    tar.resize(tar.len() + 1024, 0);
+    let dir = tempfile::tempdir().unwrap();
+    let res = extract_bounded(&tar[..], dir.path(), limits).await;
+    (dir, res)
+}
+
+async fn unpack(tar: Vec<u8>) -> (tempfile::TempDir, Result<(), String>) {
+    unpack_with(tar, &Archive::default()).await
+}
+
+#[async_std::test]
+async fn plain_files_are_unpacked() {
+    let mut tar = vec![];
+    file(&mut tar, "graders/data/words.txt", "words");
+    link(&mut tar, "graders/words.txt", "data/words.txt");
+    let (dir, res) = unpack(tar).await;
+    res.unwrap();
+    let words = dir.path().join("graders/words.txt");
+    assert_eq!(std::fs::read_to_string(words).unwrap(), "words");
+}
+
+#[async_std::test]
+async fn absolute_paths_are_rejected() {
+    let mut tar = vec![];
+    file(&mut tar, "/tmp/pms-slave-absolute", "x");
+    assert!(unpack(tar).await.1.is_err());
+    assert!(!Path::new("/tmp/pms-slave-absolute").exists());
+}
+
+#[async_std::test]
+async fn parent_dirs_are_rejected() {
+    let mut tar = vec![];
+    file(&mut tar, "graders/../../escaped", "x");
+    let (dir, res) = unpack(tar).await;
+    assert!(res.is_err());
+    assert!(!dir.path().parent().unwrap().join("escaped").exists());
+
+    let mut tar = vec![];
+    link(&mut tar, "graders/up", "../..");
+    assert!(unpack(tar).await.1.is_err());
+}
+
+#[async_std::test]
+async fn link_chains_cannot_escape() {
+    let mut tar = vec![];
+    link(&mut tar, "graders/a/b/up", "../..");
+    link(&mut tar, "graders/a/b/in", "up/a");
+    let (dir, res) = unpack(tar.clone()).await;
+    res.unwrap();
+    assert!(dir.path().join("graders/a/b/in/b").is_dir());
+
+    // Only `up` makes this leave the root, its text alone stays inside
+    link(&mut tar, "graders/a/b/esc", "up/../../../..");
+    assert!(unpack(tar).await.1.is_err());
+
+    // `c` could become a link later, so nothing may follow it while missing
+    let mut tar = vec![];
+    link(&mut tar, "graders/x", "c/../..");
+    link(&mut tar, "graders/c", ".");
+    assert!(unpack(tar).await.1.is_err());
+}
+
+#[async_std::test]
+async fn files_are_not_written_through_links() {
+    let mut tar = vec![];
+    link(&mut tar, "graders/here", ".");
+    file(&mut tar, "graders/here/x", "x");
+    assert!(unpack(tar).await.1.is_err());
+}
+
+#[async_std::test]
+async fn device_files_are_rejected() {
+    let mut tar = vec![];
+    entry(&mut tar, EntryType::Char, "graders/null", "", &[]);
+    assert!(unpack(tar).await.1.is_err());
+}
+
+#[async_std::test]
+async fn limits_are_enforced() {
+    let limits = Archive {
+        max_size: 16,
+        max_entries: 2,
+    };
+    let mut tar = vec![];
+    file(&mut tar, "a", "0123456789");
+    file(&mut tar, "b", "0123456789");
+    let (_, res) = unpack_with(tar, &limits).await;
+    assert!(res.unwrap_err().contains("larger"));
+
+    let mut tar = vec![];
+    for name in ["a", "b", "c"] {
+        file(&mut tar, name, "");
+    }
+    let (_, res) = unpack_with(tar, &limits).await;
+    assert!(res.unwrap_err().contains("entries"));
+
+    // Long names are buffered by async-tar and never reach the entry checks
+    let mut tar = vec![];
+    entry(
+        &mut tar,
+        EntryType::GNULongName,
+        "././@LongLink",
+        "",
+        &[b'a'; 8192],
+    );
+    file(&mut tar, "a", "");
+    let (_, res) = unpack_with(tar, &limits).await;
+    assert!(res.unwrap_err().contains("larger"));
+}
+
+#[async_std::test]
+async fn unbounded_extract_keeps_entry_limits() {
+    let limits = Archive {
+        max_size: 1024,
+        max_entries: 1,
+    };
+    let mut tar = vec![];
+    file(&mut tar, "a", "");
+    file(&mut tar, "b", "");
+    tar.resize(tar.len() + 1024, 0);
+    let dir = tempfile::tempdir().unwrap();
+    assert!(extract(&tar[..], dir.path(), &limits).await.is_err());
+}
@@ -0,0 +1,93 @@
+use judge_protocol::judge::{Diagnostic, DiagnosticSeverity};
+
+use crate::diagnostics::DiagnosticsFormat;
+
+/// `diagnostic` as `(file, line, column, severity, message)`
+fn fields(diagnostic: &Diagnostic) -> (&str, u32, u32, &str, &str) {
+    let severity = match diagnostic.severity {
+        DiagnosticSeverity::Error => "error",
+        DiagnosticSeverity::Warning => "warning",
+        DiagnosticSeverity::Note => "note",
+    };
+    (
+        &diagnostic.file,
+        diagnostic.line,
+        diagnostic.column,
+        severity,
+        &diagnostic.message,
+    )
+}
+
+#[test]
+fn gnu_fatal_error() {
+    let output = "main.cpp:1:10: fatal error: bits/stdc++.hpp: No such file or directory\n\
+                  compilation terminated.\n";
+    let (text, diagnostics) = DiagnosticsFormat::Gnu.parse(output);
+    assert_eq!(text, output);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        fields(&diagnostics[0]),
+        (
+            "main.cpp",
+            1,
+            10,
+            "error",
+            "bits/stdc++.hpp: No such file or directory"
+        )
+    );
+}
+
+#[test]
+fn json_children_are_flattened() {
+    let output = concat!(
+        "make: Entering directory '/box'\n",
+        r#"[{"kind": "error", "message": "'foo' was not declared in this scope", "#,
+        r#""locations": [{"caret": {"file": "main.cpp", "line": 3, "column": 5}}], "#,
+        r#""children": [{"kind": "note", "message": "suggested alternative: 'for'", "#,
+        r#""locations": [{"caret": {"file": "main.cpp", "line": 3, "column": 5}}]}]}]"#,
+        "\n"
+    );
+    let (text, diagnostics) = DiagnosticsFormat::Json.parse(output);
+    assert_eq!(
+        diagnostics.iter().map(fields).collect::<Vec<_>>(),
+        vec![
+            (
+                "main.cpp",
+                3,
+                5,
+                "error",
+                "'foo' was not declared in this scope"
+            ),
+            ("main.cpp", 3, 5, "note", "suggested alternative: 'for'"),
+        ]
+    );
+    assert_eq!(
+        text,
+        "make: Entering directory '/box'\n\
+         main.cpp:3:5: error: 'foo' was not declared in this scope\n\
+         main.cpp:3:5: note: suggested alternative: 'for'\n"
+    );
+}
+
+#[test]
+fn regex_without_column() {
+    let format = DiagnosticsFormat::Regex {
+        pattern: String::from(
+            r"^(?P<file>.+)\((?P<line>\d+)\): (?P<severity>\w+): (?P<message>.*)$",
+        ),
+    };
+    assert!(format.validate().is_ok());
+    let (_, diagnostics) = format.parse("Main.cs(7): warning: unused variable 'x'\nBuild failed\n");
+    assert_eq!(
+        diagnostics.iter().map(fields).collect::<Vec<_>>(),
+        vec![("Main.cs", 7, 0, "warning", "unused variable 'x'")]
+    );
+}
+
+#[test]
+fn regex_needs_required_groups() {
+    let format = DiagnosticsFormat::Regex {
+        pattern: String::from(r"^(?P<file>.+):(?P<line>\d+)$"),
+    };
+    assert!(format.validate().is_err());
+}
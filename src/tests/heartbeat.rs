@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::config::Reconnect;
+use crate::timer::{Backoff, Heartbeat, Latency};
+
+#[test]
+fn pongs_answer_older_pings() {
+    let mut heartbeat = Heartbeat::default();
+    let (first, second) = (heartbeat.ping(), heartbeat.ping());
+    std::thread::sleep(Duration::from_millis(1));
+    assert!(heartbeat.is_timed_out(Duration::ZERO));
+    assert!(!heartbeat.is_timed_out(Duration::from_secs(60)));
+    assert!(heartbeat.pong(second + 1).is_none());
+    assert!(heartbeat.pong(second).is_some());
+    assert!(heartbeat.pong(first).is_none());
+    assert!(!heartbeat.is_timed_out(Duration::ZERO));
+}
+
+#[test]
+fn latency_keeps_extremes_and_average() {
+    let mut latency = Latency::default();
+    for ms in [10, 30, 20] {
+        latency.record(Duration::from_millis(ms));
+    }
+    assert_eq!(latency.samples, 3);
+    assert_eq!(latency.last, Some(Duration::from_millis(20)));
+    assert_eq!(latency.min, Some(Duration::from_millis(10)));
+    assert_eq!(latency.max, Some(Duration::from_millis(30)));
+    let average = latency.average.unwrap();
+    assert!(average > Duration::from_millis(10) && average < Duration::from_millis(30));
+    assert!(latency
+        .to_string()
+        .starts_with("last 20.0 ms, min 10.0 ms"));
+}
+
+#[test]
+fn backoff_grows_up_to_cap_and_resets() {
+    let config = Reconnect {
+        initial_delay: 1.0,
+        max_delay: 5.0,
+        multiplier: 2.0,
+        jitter: 0.5,
+    };
+    let mut backoff = Backoff::new(&config);
+    for max in [1.0, 2.0, 4.0, 5.0, 5.0] {
+        let delay = backoff.next_delay().as_secs_f64();
+        assert!(delay >= max * 0.5 - 1e-9 && delay <= max + 1e-9);
+    }
+    assert_eq!(backoff.attempts(), 5);
+    backoff.reset();
+    assert!(backoff.next_delay() <= Duration::from_secs(1));
+}
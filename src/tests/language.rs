@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::SHELL;
+use crate::container::{CompileRunResult, RunMeta};
+use crate::language::{Args, CompileOutput, Language};
+
+/// A shell language with `extra` TOML lines.
+fn shell(extra: &str) -> Language {
+    toml::from_str(&format!(
+        r#"
+        uuid = "{}"
+        name = "Shell"
+        version = "POSIX sh"
+        entry_source = "main.sh"
+        add_mem_limit = 0
+        add_time_limit = 0
+        {}
+        "#,
+        SHELL, extra
+    ))
+    .unwrap()
+}
+
+fn probing(probe: &str) -> Language {
+    shell(&format!(
+        "exec_cmd = \"/bin/sh {{file}}\"\nversion_probe = \"{}\"",
+        probe
+    ))
+}
+
+#[test]
+fn args_are_split_before_rendering() {
+    let lang = shell("exec_cmd = \"/bin/sh {file}\"");
+    let infile = PathBuf::from("/box/my dir/main.cpp");
+    let render = |args: Args| {
+        lang.parse_compile_args(&args, infile.clone(), PathBuf::from("main"), PathBuf::new())
+    };
+    let expected = vec!["-O2", "-DNAME=a b", "/box/my dir/main.cpp"];
+    assert_eq!(
+        render(Args::Line(String::from("-O2 -DNAME=\"a b\" {infile}"))),
+        expected
+    );
+    assert_eq!(
+        render(Args::Line(String::from("-O2 '-DNAME=a b' {infile}"))),
+        expected
+    );
+    let argv = ["-O2", "-DNAME=a b", "{infile}"].map(String::from).to_vec();
+    assert_eq!(render(Args::Argv(argv)), expected);
+    assert_eq!(
+        lang.parse_exec_cmd(PathBuf::from("/box/a b")),
+        vec!["/bin/sh", "/box/a b"]
+    );
+}
+
+#[test]
+fn unknown_placeholders_fail_on_load() {
+    assert!(shell("exec_cmd = \"/bin/sh {file}\"").validate().is_ok());
+    assert!(shell("exec_cmd = \"{file} {input}\"").validate().is_err());
+    let lang = shell(
+        "exec_cmd = \"/bin/sh {file}\"\ncheck_exec = \"/bin/sh\"\ncheck_args = \"-n {infle}\"",
+    );
+    assert!(lang.validate().is_err());
+    assert!(shell("exec_cmd = \"/bin/sh '{file}\"").validate().is_err());
+}
+
+#[test]
+fn probe_reports_first_line() {
+    let lang = probing("/bin/sh -c 'echo; echo sh 1.0; echo more'");
+    assert_eq!(
+        lang.probe_toolchain_within(Duration::from_secs(10)),
+        Ok(Some(String::from("sh 1.0")))
+    );
+    assert!(probing("/bin/false").probe_toolchain().is_err());
+}
+
+#[test]
+fn hanging_probe_is_killed() {
+    let started = Instant::now();
+    let res = probing("/bin/sleep 30").probe_toolchain_within(Duration::from_millis(100));
+    assert!(res.unwrap_err().contains("did not finish"));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn bundled_languages_are_valid() {
+    for entry in std::fs::read_dir(crate::constants::LANGUAGES_PATH).unwrap() {
+        let path = entry.unwrap().path();
+        let lang: Language = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(lang.validate().is_ok(), "{}", path.display());
+    }
+}
+
+#[test]
+fn sandbox_paths_are_stripped() {
+    let res = CompileRunResult {
+        meta: RunMeta::default(),
+        stdout: b"/tmp/pms-work/main.cpp: In function 'int main()':\n".to_vec(),
+        stderr: b"/box/main.cpp:3:5: error: 'foo' was not declared in this scope\n".to_vec(),
+    };
+    let output = CompileOutput::from_run(&res, Path::new("/tmp/pms-work"));
+    assert_eq!(output.stdout, "main.cpp: In function 'int main()':\n");
+    assert_eq!(
+        output.stderr,
+        "main.cpp:3:5: error: 'foo' was not declared in this scope\n"
+    );
+}
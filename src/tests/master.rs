@@ -0,0 +1,402 @@
+//! A mock master driving a slave over a local socket, as the real one would.
+
+use async_std::future::timeout;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::sync::{Arc, Mutex};
+use async_std::task::spawn;
+use async_tar::{Builder, Header};
+use bincode::Options;
+use judge_protocol::handshake::*;
+use judge_protocol::judge::*;
+use judge_protocol::packet::*;
+use judge_protocol::security::*;
+use k256::ecdh::{EphemeralSecret, SharedSecret};
+use rand::thread_rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::SHELL;
+use crate::capability::Capabilities;
+use crate::protocol::serve;
+use crate::session::JudgeSession;
+use crate::timer::Backoff;
+use crate::{CONFIG, MASTER_PASS};
+
+const DOUBLE: &str = "read n\necho $((n * 2))\n";
+const CHECKER: &str = "[ \"$(cat \"$2\")\" = \"$(cat \"$3\")\" ]\n";
+const STATE_TIMEOUT: u64 = 30; // in secs
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+}
+
+struct Master {
+    stream: Arc<TcpStream>,
+    shared: SharedSecret,
+}
+
+impl Master {
+    /// Starts a slave connected to a new master and completes the handshake.
+    async fn start() -> Self {
+        Self::connect(Arc::new(Mutex::new(JudgeSession::default()))).await
+    }
+
+    /// Connects a slave holding `session` to a new master, as after a reconnect.
+    async fn connect(session: Arc<Mutex<JudgeSession>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let backoff = Arc::new(Mutex::new(Backoff::new(&CONFIG.reconnect)));
+        spawn(async move {
+            serve(TcpStream::connect(addr).await.unwrap(), session, backoff).await
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        let stream = Arc::new(stream);
+        let req: HandshakeRequest = Self::read(&stream, Command::Handshake).await;
+        assert_eq!(req.pass, *MASTER_PASS);
+        let secret = EphemeralSecret::random(thread_rng());
+        let master = Self {
+            stream,
+            shared: secret.diffie_hellman(&req.client_pubkey),
+        };
+        let res = HandshakeResponse {
+            result: HandshakeResult::Success,
+            node_id: Some(1),
+            server_pubkey: Some(secret.public_key()),
+        };
+        master.send(Command::Handshake, &res).await;
+        Self::read::<BodyAfterHandshake<Capabilities>>(&master.stream, Command::UpdateCapabilities)
+            .await;
+        master
+    }
+
+    /// Reads packets until one with `command` arrives.
+    async fn read<T: DeserializeOwned>(stream: &Arc<TcpStream>, command: Command) -> T {
+        loop {
+            let packet = timeout(
+                Duration::from_secs(STATE_TIMEOUT),
+                Packet::from_stream(Arc::clone(stream)),
+            )
+            .await
+            .expect("The slave did not answer")
+            .unwrap();
+            if std::mem::discriminant(&packet.heady.header.command)
+                == std::mem::discriminant(&command)
+            {
+                return options().deserialize(&packet.heady.body).unwrap();
+            }
+        }
+    }
+
+    async fn send<T: Serialize>(&self, command: Command, body: &T) {
+        Packet::make_packet(command, options().serialize(body).unwrap())
+            .send(Arc::clone(&self.stream))
+            .await
+            .unwrap();
+    }
+
+    fn encrypt(&self, data: &str) -> Encrypted {
+        Encrypted::encrypt(data.as_bytes(), &expand_key(&self.shared))
+    }
+
+    /// The next state reported for judge `uuid`.
+    async fn state(&self, uuid: Uuid) -> JudgeState {
+        let body: BodyAfterHandshake<JudgeResponseBody> =
+            Self::read(&self.stream, Command::GetJudgeStateUpdate).await;
+        assert_eq!(body.req.uuid, uuid);
+        body.req.result
+    }
+
+    async fn judge(&self, uuid: Uuid, main_code: &str) {
+        let req = JudgeRequestBody {
+            uuid,
+            main_lang: SHELL,
+            checker_lang: SHELL,
+            main_code: self.encrypt(main_code),
+            checker_code: self.encrypt(CHECKER),
+            checker_hash: None,
+            time_limit: 1000,
+            mem_limit: 262144,
+        };
+        self.send(Command::GetJudge, &req).await;
+    }
+
+    async fn test(&self, uuid: Uuid, test_uuid: Uuid, stdin: &str, stdout: &str) {
+        self.test_hashed(uuid, test_uuid, stdin, stdout, false)
+            .await;
+    }
+
+    /// Sends a test by the hashes of its data, and the data too unless `hash_only`.
+    async fn test_hashed(
+        &self,
+        uuid: Uuid,
+        test_uuid: Uuid,
+        stdin: &str,
+        stdout: &str,
+        hash_only: bool,
+    ) {
+        let data = if hash_only { ("", "") } else { (stdin, stdout) };
+        let hash = |s: &str| Some(blake3::hash(s.as_bytes()).to_hex().to_string());
+        let body = TestCaseUpdateBody {
+            uuid,
+            test_uuid,
+            stdin: self.encrypt(data.0),
+            stdout: self.encrypt(data.1),
+            stdin_hash: hash(stdin),
+            stdout_hash: hash(stdout),
+        };
+        self.send(Command::TestCaseUpdate, &body).await;
+    }
+}
+
+#[async_std::test]
+async fn simple_judge_reports_verdicts() {
+    let master = Master::start().await;
+    let uuid = Uuid::new_v4();
+    master.judge(uuid, DOUBLE).await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+
+    let other = Uuid::new_v4();
+    master.judge(other, DOUBLE).await;
+    assert!(matches!(master.state(other).await, JudgeState::LockedSlave));
+
+    let test = Uuid::new_v4();
+    master.test(uuid, test, "21\n", "42\n").await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::Accepted(t, _, _) if t == test
+    ));
+    master.test(uuid, test, "21\n", "43\n").await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::WrongAnswer(t, _, _) if t == test
+    ));
+    master.test(other, test, "21\n", "42\n").await;
+    assert!(matches!(
+        master.state(other).await,
+        JudgeState::JudgeNotFound
+    ));
+}
+
+#[async_std::test]
+async fn test_data_is_taken_from_store() {
+    let master = Master::start().await;
+    let uuid = Uuid::new_v4();
+    master.judge(uuid, DOUBLE).await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+
+    // Unique data, so no other test has stored it
+    let n = Uuid::new_v4().as_u128() % 1000000;
+    let (stdin, stdout) = (format!("{}\n", n), format!("{}\n", n * 2));
+    let test = Uuid::new_v4();
+    master.test_hashed(uuid, test, &stdin, &stdout, true).await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::ArtifactNotFound(missing) if missing.len() == 2
+    ));
+    master.test(uuid, test, &stdin, &stdout).await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::Accepted(t, _, _) if t == test
+    ));
+    master.test_hashed(uuid, test, &stdin, &stdout, true).await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::Accepted(t, _, _) if t == test
+    ));
+}
+
+#[async_std::test]
+async fn compile_error_unlocks_slave() {
+    let master = Master::start().await;
+    let uuid = Uuid::new_v4();
+    master.judge(uuid, "if then\n").await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompileError(message) if !message.is_empty()
+    ));
+
+    let uuid = Uuid::new_v4();
+    master.judge(uuid, DOUBLE).await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+}
+
+#[async_std::test]
+async fn pings_are_answered() {
+    let master = Master::start().await;
+    master.send(Command::Ping, &7u64).await;
+    let pong: BodyAfterHandshake<u64> = Master::read(&master.stream, Command::Pong).await;
+    assert_eq!(pong.req, 7);
+}
+
+#[async_std::test]
+async fn judge_is_resumed_after_reconnect() {
+    let session = Arc::new(Mutex::new(JudgeSession::default()));
+    let master = Master::connect(Arc::clone(&session)).await;
+    let uuid = Uuid::new_v4();
+    master.judge(uuid, DOUBLE).await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+    drop(master);
+
+    let master = Master::connect(session).await;
+    let resumed: BodyAfterHandshake<Uuid> =
+        Master::read(&master.stream, Command::ResumeJudge).await;
+    assert_eq!(resumed.req, uuid);
+    let test = Uuid::new_v4();
+    master.test(uuid, test, "4\n", "8\n").await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::Accepted(t, _, _) if t == test
+    ));
+}
+
+#[async_std::test]
+async fn judge_not_resumed_is_dropped() {
+    let session = Arc::new(Mutex::new(JudgeSession::default()));
+    let master = Master::connect(Arc::clone(&session)).await;
+    let uuid = Uuid::new_v4();
+    master.judge(uuid, DOUBLE).await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+    drop(master);
+
+    // A restarted master no longer knows the judge and ignores the resume
+    let master = Master::connect(session).await;
+    Master::read::<BodyAfterHandshake<Uuid>>(&master.stream, Command::ResumeJudge).await;
+    let other = Uuid::new_v4();
+    master.judge(other, DOUBLE).await;
+    assert!(matches!(master.state(other).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(other).await,
+        JudgeState::CompleteCompile(_)
+    ));
+    let test = Uuid::new_v4();
+    master.test(uuid, test, "4\n", "8\n").await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::JudgeNotFound
+    ));
+}
+
+/// A plain tar of `files` as `(path, content)`.
+async fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for (path, content) in files {
+        let mut header = Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, content.as_bytes())
+            .await
+            .unwrap();
+    }
+    builder.into_inner().await.unwrap()
+}
+
+/// Sends a novel judge with `graders` as its archive.
+async fn novel_judge(master: &Master, uuid: Uuid, graders: &[(&str, &str)]) {
+    let graders = archive(graders).await;
+    let req = JudgeRequestBodyv2 {
+        uuid,
+        main_lang: SHELL,
+        checker_lang: SHELL,
+        manager_lang: SHELL,
+        main_code: master.encrypt(DOUBLE),
+        checker_code: master.encrypt(CHECKER),
+        manager_code: master.encrypt("exit 0\n"),
+        checker_hash: None,
+        manager_hash: None,
+        graders: Encrypted::encrypt(&graders, &expand_key(&master.shared)),
+        object_path: String::new(),
+        main_path: String::new(),
+        time_limit: 1000,
+        mem_limit: 262144,
+        procs: 1,
+    };
+    master.send(Command::GetJudgev2, &req).await;
+}
+
+#[async_std::test]
+async fn novel_judge_builds_graders() {
+    let master = Master::start().await;
+    let uuid = Uuid::new_v4();
+    novel_judge(
+        &master,
+        uuid,
+        &[
+            (
+                "manifest.toml",
+                "source = \"main.sh\"\nobject = \"grader.sh\"\n",
+            ),
+            (
+                "graders/build.toml",
+                "backend = \"script\"\npath = \"build.sh\"\n",
+            ),
+            ("graders/build.sh", "cp main.sh grader.sh\n"),
+        ],
+    )
+    .await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+
+    let test = Uuid::new_v4();
+    master.test(uuid, test, "5\n", "10\n").await;
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::Accepted(t, _, _) if t == test
+    ));
+}
+
+#[async_std::test]
+async fn nested_source_is_installed() {
+    let master = Master::start().await;
+    let uuid = Uuid::new_v4();
+    novel_judge(
+        &master,
+        uuid,
+        &[
+            (
+                "manifest.toml",
+                "source = \"src/main.sh\"\nobject = \"grader.sh\"\n",
+            ),
+            (
+                "graders/build.toml",
+                "backend = \"script\"\npath = \"build.sh\"\n",
+            ),
+            ("graders/build.sh", "cp src/main.sh grader.sh\n"),
+        ],
+    )
+    .await;
+    assert!(matches!(master.state(uuid).await, JudgeState::DoCompile));
+    assert!(matches!(
+        master.state(uuid).await,
+        JudgeState::CompleteCompile(_)
+    ));
+}
@@ -1 +1,60 @@
+//! Tests run the slave against a mock master, with `sandbox::HostSandbox`
+//! in place of isolate and the configuration and languages below.
 
+mod archive;
+mod diagnostics;
+mod heartbeat;
+mod language;
+mod master;
+pub mod sandbox;
+mod session;
+
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::language::{Language, Languages};
+
+/// `/bin/sh` scripts, checked with `sh -n` instead of compiled
+pub const SHELL: Uuid = Uuid::from_u128(0x5348_454c_4c00_4000_8000_0000_0000_0001);
+
+/// No cache, and stores in a temporary directory shared by every test.
+pub fn config() -> Config {
+    let store = std::env::temp_dir().join(format!("pms-slave-test-{}", std::process::id()));
+    toml::from_str(&format!(
+        r#"
+        [host]
+        master = "127.0.0.1:3030"
+        master_pass = "test"
+
+        [redis]
+        enabled = false
+
+        [cache]
+        backend = "none"
+
+        [store]
+        path = "{}"
+        "#,
+        store.display()
+    ))
+    .unwrap()
+}
+
+pub fn languages() -> Languages {
+    let shell = toml::from_str::<Language>(&format!(
+        r#"
+        uuid = "{}"
+        name = "Shell"
+        version = "POSIX sh"
+        exec_cmd = "/bin/sh {{file}}"
+        check_exec = "/bin/sh"
+        check_args = "-n {{infile}}"
+        entry_source = "main.sh"
+        add_mem_limit = 0
+        add_time_limit = 0
+        "#,
+        SHELL
+    ))
+    .unwrap();
+    Languages::with(vec![shell])
+}
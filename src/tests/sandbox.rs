@@ -0,0 +1,116 @@
+//! Runs judge processes directly on the host so the pipeline can be
+//! exercised without isolate. Nothing is limited or isolated.
+
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Instant;
+
+use crate::constants::*;
+use crate::container::*;
+
+pub struct HostSandbox;
+
+/// Maps an argument inside the sandbox to the directory mounted as `/box`.
+fn host_arg(arg: &str, work_dir: &Path) -> String {
+    match arg.strip_prefix(SANDBOX_ROOT) {
+        Some(rest) => format!("{}{}", work_dir.display(), rest),
+        None => arg.to_string(),
+    }
+}
+
+fn meta(status: ExitStatus, started: Instant) -> RunMeta {
+    use std::os::unix::process::ExitStatusExt;
+    let time = started.elapsed().as_secs_f64();
+    RunMeta {
+        status: if status.success() {
+            None
+        } else if status.signal().is_some() {
+            Some(RunStatus::DiedOnSignal)
+        } else {
+            Some(RunStatus::RuntimeErr)
+        },
+        time: Some(time),
+        time_wall: Some(time),
+        message: None,
+        max_rss: None,
+        killed: None,
+        exitsig: status.signal(),
+        exitcode: status.code(),
+        csw_voluntary: None,
+        csw_forced: None,
+        cg_mem: Some(0),
+        cg_oom_killed: None,
+    }
+}
+
+/// Runs `argv` in `temp_path` on the test input, writing the output next to it.
+fn run_on_test(argv: &[String], temp_path: &Path) -> RunResult {
+    let started = Instant::now();
+    let status = Command::new(&argv[0])
+        .args(&argv[1..])
+        .current_dir(temp_path)
+        .stdin(File::open(temp_path.join(STDIN_FILE_NAME)).unwrap())
+        .stdout(File::create(temp_path.join(STDOUT_FILE_NAME)).unwrap())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    RunResult {
+        meta: meta(status, started),
+    }
+}
+
+impl Sandbox for HostSandbox {
+    fn compile(&self, run: &CompileRun) -> CompileRunResult {
+        let argv: Vec<String> = run
+            .argv
+            .iter()
+            .map(|arg| host_arg(arg, &run.work_dir))
+            .collect();
+        let started = Instant::now();
+        let out = Command::new(&argv[0])
+            .args(&argv[1..])
+            .current_dir(&run.work_dir)
+            .output()
+            .unwrap();
+        CompileRunResult {
+            meta: meta(out.status, started),
+            stdout: out.stdout,
+            stderr: out.stderr,
+        }
+    }
+
+    fn run(&self, run: &Run) -> RunResult {
+        let argv = run.language.parse_exec_cmd(run.temp_path.join(BINARY_NAME));
+        run_on_test(&argv, &run.temp_path)
+    }
+
+    /// The manager is not started; the grader object reads the test itself.
+    fn run_v2(&self, run: &Runv2) -> RunResult {
+        let object = run.temp_path.join(GRADERS_PATH).join(&run.object_path);
+        let argv = run.main_lang.parse_exec_cmd(object);
+        run_on_test(&argv, &run.temp_path)
+    }
+
+    /// Checkers get the input, the output and the answer, and accept by exiting with 0.
+    fn check(&self, run: &CheckerRun) -> CheckerResult {
+        let mut argv = run
+            .checker_lang
+            .parse_exec_cmd(run.temp_path.join(CHECKER_NAME));
+        for name in [STDIN_FILE_NAME, STDOUT_FILE_NAME, STDOUT_ORIGIN_FILE_NAME] {
+            argv.push(run.temp_path.join(name).display().to_string());
+        }
+        let started = Instant::now();
+        let status = Command::new(&argv[0])
+            .args(&argv[1..])
+            .current_dir(&run.temp_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        CheckerResult {
+            score: None,
+            meta: meta(status, started),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use judge_protocol::judge::JudgeState;
+use uuid::Uuid;
+
+use crate::container::{CheckerResult, RunMeta, RunStatus};
+use crate::judge::{checker_verdict, run_verdict, OnJudge};
+use crate::language::Language;
+use crate::session::JudgeSession;
+
+fn language() -> Language {
+    toml::from_str(include_str!("../../langs/cpp.toml")).unwrap()
+}
+
+fn on_judge(uuid: Uuid) -> OnJudge {
+    OnJudge {
+        uuid,
+        main_lang: language(),
+        checker_lang: language(),
+        manager_lang: None,
+        main_binary: Default::default(),
+        checker_binary: Default::default(),
+        object_binary: None,
+        grader_files: vec![],
+        time_limit: 1000,
+        mem_limit: 262144,
+        tempdir: tempfile::tempdir().unwrap(),
+        procs: 1,
+    }
+}
+
+fn meta(status: Option<RunStatus>) -> RunMeta {
+    RunMeta {
+        status,
+        time: Some(0.25),
+        time_wall: None,
+        message: None,
+        max_rss: None,
+        killed: None,
+        exitsig: Some(11),
+        exitcode: Some(1),
+        csw_voluntary: None,
+        csw_forced: None,
+        cg_mem: Some(1024),
+        cg_oom_killed: None,
+    }
+}
+
+#[test]
+fn session_runs_tests_of_compiled_judge() {
+    let (uuid, test) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut session = JudgeSession::default();
+    session.begin_compile(uuid).unwrap();
+    assert!(matches!(
+        session.begin_test(uuid, test),
+        Err(JudgeState::JudgeNotFound)
+    ));
+    session.compiled(on_judge(uuid)).unwrap();
+    assert!(matches!(
+        session.begin_test(Uuid::new_v4(), test),
+        Err(JudgeState::JudgeNotFound)
+    ));
+    assert_eq!(session.begin_test(uuid, test).unwrap().uuid, uuid);
+    assert!(matches!(
+        session.begin_test(uuid, Uuid::new_v4()),
+        Err(JudgeState::LockedSlave)
+    ));
+    session.end_test(uuid, Uuid::new_v4());
+    assert!(session.begin_test(uuid, Uuid::new_v4()).is_err());
+    session.end_test(uuid, test);
+    let next = Uuid::new_v4();
+    assert!(session.begin_test(uuid, next).is_ok());
+    session.end_test(uuid, next);
+    assert_eq!(session.uuid(), Some(uuid));
+}
+
+#[test]
+fn session_can_end_judge_while_testing() {
+    let (uuid, test) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut session = JudgeSession::default();
+    session.begin_compile(uuid).unwrap();
+    session.compiled(on_judge(uuid)).unwrap();
+    let judge = session.begin_test(uuid, test).unwrap();
+    session.finish();
+    assert_eq!(session.uuid(), None);
+    let other = Uuid::new_v4();
+    session.begin_compile(other).unwrap();
+    session.compiled(on_judge(other)).unwrap();
+    session.end_test(judge.uuid, test);
+    assert!(session.begin_test(other, Uuid::new_v4()).is_ok());
+}
+
+#[test]
+fn session_is_locked_until_finished() {
+    let uuid = Uuid::new_v4();
+    let mut session = JudgeSession::default();
+    session.begin_compile(uuid).unwrap();
+    assert!(matches!(
+        session.begin_compile(Uuid::new_v4()),
+        Err(JudgeState::LockedSlave)
+    ));
+    session.compiled(on_judge(uuid)).unwrap();
+    assert!(matches!(
+        session.begin_compile(Uuid::new_v4()),
+        Err(JudgeState::LockedSlave)
+    ));
+    session.finish();
+    assert_eq!(session.uuid(), None);
+    assert!(matches!(
+        session.begin_test(uuid, Uuid::new_v4()),
+        Err(JudgeState::UnlockedSlave)
+    ));
+    assert!(session.begin_compile(Uuid::new_v4()).is_ok());
+}
+
+#[test]
+fn session_drops_judge_not_resumed() {
+    let uuid = Uuid::new_v4();
+    let mut session = JudgeSession::default();
+    session.begin_compile(uuid).unwrap();
+    session.compiled(on_judge(uuid)).unwrap();
+    assert_eq!(session.resume(), Some(uuid));
+    let test = Uuid::new_v4();
+    session.begin_test(uuid, test).unwrap();
+    session.end_test(uuid, test);
+    assert!(matches!(
+        session.begin_compile(Uuid::new_v4()),
+        Err(JudgeState::LockedSlave)
+    ));
+
+    assert_eq!(session.resume(), Some(uuid));
+    let other = Uuid::new_v4();
+    session.begin_compile(other).unwrap();
+    assert_eq!(session.uuid(), Some(other));
+}
+
+#[test]
+fn session_unlocks_after_failed_compile() {
+    let uuid = Uuid::new_v4();
+    let mut session = JudgeSession::default();
+    session.begin_compile(uuid).unwrap();
+    session.abort(Uuid::new_v4());
+    assert_eq!(session.uuid(), Some(uuid));
+    session.abort(uuid);
+    assert_eq!(session.uuid(), None);
+    assert!(session.begin_compile(uuid).is_ok());
+}
+
+#[test]
+fn session_drops_judge_ended_while_compiling() {
+    let uuid = Uuid::new_v4();
+    let mut session = JudgeSession::default();
+    session.begin_compile(uuid).unwrap();
+    session.finish();
+    assert!(matches!(
+        session.compiled(on_judge(uuid)),
+        Err(JudgeState::JudgeNotFound)
+    ));
+    assert_eq!(session.uuid(), None);
+}
+
+#[test]
+fn failed_runs_are_not_checked() {
+    let test = Uuid::new_v4();
+    assert!(run_verdict(test, &meta(None)).is_none());
+    assert!(matches!(
+        run_verdict(test, &meta(Some(RunStatus::TimedOut))),
+        Some(JudgeState::TimeLimitExceed(t)) if t == test
+    ));
+    assert!(matches!(
+        run_verdict(test, &meta(Some(RunStatus::DiedOnSignal))),
+        Some(JudgeState::DiedOnSignal(t, 11)) if t == test
+    ));
+    assert!(matches!(
+        run_verdict(test, &meta(Some(RunStatus::RuntimeErr))),
+        Some(JudgeState::RuntimeError(t, 1)) if t == test
+    ));
+    assert!(matches!(
+        run_verdict(test, &meta(Some(RunStatus::InternalErr))),
+        Some(JudgeState::InternalError(t)) if t == test
+    ));
+    assert!(matches!(
+        run_verdict(test, &meta(Some(RunStatus::Unknown))),
+        Some(JudgeState::UnknownError)
+    ));
+}
+
+#[test]
+fn checker_decides_checked_runs() {
+    let test = Uuid::new_v4();
+    let checked = |status, score| CheckerResult {
+        score,
+        meta: meta(status),
+    };
+    assert!(matches!(
+        checker_verdict(test, &checked(None, None)),
+        JudgeState::Accepted(t, 250, 1024) if t == test
+    ));
+    assert!(matches!(
+        checker_verdict(test, &checked(Some(RunStatus::RuntimeErr), None)),
+        JudgeState::WrongAnswer(t, 250, 1024) if t == test
+    ));
+    assert!(matches!(
+        checker_verdict(test, &checked(None, Some(0.5))),
+        JudgeState::Complete(t, score, 250, 1024) if t == test && score == 0.5
+    ));
+}
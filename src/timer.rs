@@ -1,20 +1,117 @@
-use async_std::channel::Sender;
-use async_std::net::TcpStream;
-use async_std::sync::Arc;
-use async_std::task::sleep;
-
-use std::time::Duration;
-
-use crate::constants::CHECK_ALIVE_TIME;
-use crate::protocol::Actions;
-
-pub async fn check_alive(action_tx: Sender<Actions>, stream: Arc<TcpStream>) {
-    loop {
-        sleep(Duration::from_secs(CHECK_ALIVE_TIME)).await;
-        if let Ok(val) = stream.peek(&mut vec![0]).await {
-            if val == 0 {
-                action_tx.send(Actions::Reconnect(0)).await.ok();
+use async_std::sync::Mutex;
+use rand::Rng;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::Reconnect;
+use crate::constants::LATENCY_SMOOTHING;
+
+lazy_static! {
+    /// Round-trip latency to the master, kept across reconnects
+    pub static ref LATENCY: Mutex<Latency> = Mutex::new(Latency::default());
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct Latency {
+    pub last: Option<Duration>,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub average: Option<Duration>, // Exponentially weighted
+    pub samples: u64,
+}
+
+impl Latency {
+    pub fn record(&mut self, rtt: Duration) {
+        self.last = Some(rtt);
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+        self.average = Some(match self.average {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_SMOOTHING) + rtt.mul_f64(LATENCY_SMOOTHING)
             }
+            None => rtt,
+        });
+        self.samples += 1;
+    }
+}
+
+impl std::fmt::Display for Latency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Option<Duration>| d.map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+        write!(
+            f,
+            "last {:.1} ms, min {:.1} ms, average {:.1} ms, max {:.1} ms over {} pings",
+            ms(self.last),
+            ms(self.min),
+            ms(self.average),
+            ms(self.max),
+            self.samples
+        )
+    }
+}
+
+/// Pings of one connection that the master has not answered yet.
+#[derive(Default, Debug)]
+pub struct Heartbeat {
+    next_seq: u64,
+    sent: VecDeque<(u64, Instant)>,
+}
+
+impl Heartbeat {
+    /// Returns the sequence number of a new ping.
+    pub fn ping(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.sent.push_back((seq, Instant::now()));
+        seq
+    }
+
+    /// Returns the round trip of ping `seq`. Older pings count as answered too.
+    pub fn pong(&mut self, seq: u64) -> Option<Duration> {
+        let pos = self.sent.iter().position(|(sent, _)| *sent == seq)?;
+        let (_, at) = self.sent.drain(..=pos).last()?;
+        Some(at.elapsed())
+    }
+
+    /// Whether the oldest unanswered ping is older than `timeout`.
+    pub fn is_timed_out(&self, timeout: Duration) -> bool {
+        self.sent
+            .front()
+            .map_or(false, |(_, at)| at.elapsed() > timeout)
+    }
+}
+
+/// Delays between attempts to reach the master, growing exponentially until
+/// a handshake succeeds.
+#[derive(Debug)]
+pub struct Backoff {
+    config: Reconnect,
+    attempts: i32,
+}
+
+impl Backoff {
+    pub fn new(config: &Reconnect) -> Self {
+        Self {
+            config: config.clone(),
+            attempts: 0,
         }
     }
+
+    pub fn attempts(&self) -> i32 {
+        self.attempts
+    }
+
+    /// The delay before the next attempt, with part of it randomly cut off.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = (self.config.initial_delay * self.config.multiplier.powi(self.attempts))
+            .min(self.config.max_delay);
+        self.attempts = self.attempts.saturating_add(1);
+        let jitter = rand::thread_rng().gen_range(0.0..=self.config.jitter);
+        Duration::from_secs_f64(delay * (1.0 - jitter))
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
 }